@@ -0,0 +1,152 @@
+//! Procedural macros for [verona-rt](https://docs.rs/verona-rt).
+//!
+//! This is an implementation detail of the verona-rt crate, use the macros
+//! through it's re-exports.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, ToTokens};
+use syn::{
+    parse_macro_input, punctuated::Punctuated, spanned::Spanned, Error, Expr, ItemFn, Meta,
+    ReturnType, Token,
+};
+
+/// Seeds explored by `#[verona_rt::test(systematic)]` when none are given.
+const DEFAULT_SEEDS: &str = "0..100";
+
+/// Run a test inside a scheduler session.
+///
+/// ```rust,ignore
+/// #[verona_rt::test(threads = 4, leak_check)]
+/// fn my_test() {
+///     let v = CownPtr::new(10);
+///     when(&v, |v| assert_eq!(*v, 10));
+/// }
+/// ```
+///
+/// ## Options
+///
+/// - `threads = N`: Run with `N` worker threads.
+/// - `leak_check`: Panic if anything leaked once the session is over.
+/// - `logging`: Enable the runtime's logging.
+/// - `seed = N`: Run once under systematic testing, with seed `N`.
+/// - `seeds = A..B`: Run under systematic testing once for every seed in the
+///   range, stopping at the first failure.
+/// - `systematic`: Like `seeds`, for a default range of seeds.
+///
+/// The systematic testing options need verona-rt's `systematic_testing`
+/// feature.
+///
+/// Panics inside behaviours are propagated to the test once the session is
+/// over, so `#[should_panic]` works as usual.
+#[proc_macro_attribute]
+pub fn test(args: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args with Punctuated::<Meta, Token![,]>::parse_terminated);
+    let item = parse_macro_input!(item as ItemFn);
+
+    match expand_test(args, item) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+#[derive(Default)]
+struct TestArgs {
+    threads: Option<Expr>,
+    leak_check: bool,
+    logging: bool,
+    systematic: bool,
+    seed: Option<Expr>,
+    seeds: Option<Expr>,
+}
+
+impl TestArgs {
+    fn parse(args: Punctuated<Meta, Token![,]>) -> syn::Result<Self> {
+        let mut this = Self::default();
+
+        for arg in args {
+            let name = arg.path().get_ident().map(|i| i.to_string());
+            match (name.as_deref(), arg) {
+                (Some("leak_check"), Meta::Path(_)) => this.leak_check = true,
+                (Some("logging"), Meta::Path(_)) => this.logging = true,
+                (Some("systematic"), Meta::Path(_)) => this.systematic = true,
+                (Some("threads"), Meta::NameValue(nv)) => this.threads = Some(nv.value),
+                (Some("seed"), Meta::NameValue(nv)) => this.seed = Some(nv.value),
+                (Some("seeds"), Meta::NameValue(nv)) => this.seeds = Some(nv.value),
+                (_, arg) => {
+                    return Err(Error::new(
+                        arg.span(),
+                        "unknown option, expected one of `threads = N`, `leak_check`, \
+                         `logging`, `systematic`, `seed = N` or `seeds = A..B`",
+                    ))
+                }
+            }
+        }
+
+        if let (Some(_), Some(seeds)) = (&this.seed, &this.seeds) {
+            return Err(Error::new(
+                seeds.span(),
+                "`seed` and `seeds` can't be used together",
+            ));
+        }
+
+        Ok(this)
+    }
+}
+
+fn expand_test(args: Punctuated<Meta, Token![,]>, item: ItemFn) -> syn::Result<TokenStream2> {
+    let args = TestArgs::parse(args)?;
+
+    let ItemFn {
+        attrs,
+        vis,
+        sig,
+        block,
+    } = item;
+
+    if let Some(asyncness) = sig.asyncness {
+        return Err(Error::new(asyncness.span(), "tests can't be async"));
+    }
+    if !sig.inputs.is_empty() {
+        return Err(Error::new(sig.inputs.span(), "tests can't take arguments"));
+    }
+
+    let mut builder = quote! { ::verona_rt::SchedulerBuilder::new() };
+    if let Some(threads) = &args.threads {
+        builder.extend(quote! { .threads(#threads) });
+    }
+    if args.leak_check {
+        builder.extend(quote! { .detect_leaks(true) });
+    }
+    if args.logging {
+        builder.extend(quote! { .logging(true) });
+    }
+
+    let seeds = match args.seeds {
+        Some(seeds) => Some(seeds.into_token_stream()),
+        None if args.systematic && args.seed.is_none() => Some(DEFAULT_SEEDS.parse().unwrap()),
+        None => None,
+    };
+
+    let body = if let Some(seeds) = seeds {
+        if let ReturnType::Type(_, ty) = &sig.output {
+            return Err(Error::new(
+                ty.span(),
+                "tests exploring many seeds must return `()`",
+            ));
+        }
+        quote! { ::verona_rt::systematic::explore_with(#builder, #seeds, || #block) }
+    } else if let Some(seed) = &args.seed {
+        quote! { #builder.seed(#seed).run(|| #block) }
+    } else {
+        quote! { #builder.run(|| #block) }
+    };
+
+    Ok(quote! {
+        #[::core::prelude::v1::test]
+        #(#attrs)*
+        #vis #sig {
+            #body
+        }
+    })
+}
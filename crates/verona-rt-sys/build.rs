@@ -1,4 +1,85 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
 fn main() {
+    let stdlib = CxxStdlib::from_env();
+    let sanitizers = sanitizers();
+    if cfg!(feature = "cross_lang_lto") {
+        check_linker_plugin_lto();
+    }
+
+    println!("cargo:rerun-if-env-changed=VERONA_RT_BUILD_TYPE");
+    let build_type = env::var("VERONA_RT_BUILD_TYPE").ok();
+
+    let lib_dir = match find_prebuilt() {
+        Some(lib_dir) => {
+            // The build type can't be checked, and doesn't affect the ABI.
+            if let Some(build_type) = build_type {
+                panic!(
+                    "VERONA_RT_BUILD_TYPE={build_type:?} only applies when building \
+                     verona-rt, but a prebuilt one in {} is used",
+                    lib_dir.display()
+                );
+            }
+            lib_dir
+        }
+        None => build_with_cmake(stdlib, &sanitizers, build_type),
+    };
+
+    println!("cargo:rustc-link-search=native={}", lib_dir.display());
+    println!("cargo:rustc-link-lib=static=boxcar_bindings");
+
+    write_layout(&lib_dir, stdlib, &sanitizers);
+
+    stdlib.link();
+}
+
+/// Look for a prebuilt `libboxcar_bindings.a`, if the user asked for one.
+///
+/// - `VERONA_RT_LIB_DIR` names the directory it's in.
+/// - `VERONA_RT_PKG_CONFIG=1` finds it with pkg-config.
+///
+/// Either way, the directory must be the install directory of `cpp`, so it
+/// also contains the `boxcar_layout` probe.
+fn find_prebuilt() -> Option<PathBuf> {
+    println!("cargo:rerun-if-env-changed=VERONA_RT_LIB_DIR");
+    println!("cargo:rerun-if-env-changed=VERONA_RT_PKG_CONFIG");
+
+    let lib_dir = if let Some(dir) = env::var_os("VERONA_RT_LIB_DIR") {
+        PathBuf::from(dir)
+    } else if env::var_os("VERONA_RT_PKG_CONFIG").is_some_and(|v| v == "1") {
+        let lib = pkg_config::Config::new()
+            .exactly_version(env!("CARGO_PKG_VERSION"))
+            .cargo_metadata(false)
+            .probe("boxcar_bindings")
+            .unwrap_or_else(|e| panic!("VERONA_RT_PKG_CONFIG is set, but {e}"));
+        match lib.link_paths.as_slice() {
+            [dir, ..] => dir.clone(),
+            [] => panic!("pkg-config found boxcar_bindings, but not its library directory"),
+        }
+    } else {
+        return None;
+    };
+
+    for file in ["libboxcar_bindings.a", "boxcar_layout"] {
+        if !lib_dir.join(file).is_file() {
+            panic!(
+                "prebuilt verona-rt in {} is missing {file}. \
+                 It should be the install directory of verona-rt-sys's `cpp` \
+                 cmake project.",
+                lib_dir.display()
+            );
+        }
+    }
+
+    Some(lib_dir)
+}
+
+/// Build `cpp` with cmake, returning the install directory.
+fn build_with_cmake(stdlib: CxxStdlib, sanitizers: &[&str], build_type: Option<String>) -> PathBuf {
     let mut cmake_build = cmake::Config::new("cpp");
 
     if has_ninja() {
@@ -11,26 +92,328 @@ fn main() {
     if cfg!(feature = "flight_recorder") {
         cmake_build.define("USE_CRASH_LOGGING", "ON");
     }
+    if cfg!(feature = "cross_lang_lto") {
+        configure_cross_lang_lto(&mut cmake_build);
+    }
+
+    if let Some(build_type) = build_type {
+        const BUILD_TYPES: [&str; 4] = ["Debug", "Release", "RelWithDebInfo", "MinSizeRel"];
+        assert!(
+            BUILD_TYPES.contains(&build_type.as_str()),
+            "VERONA_RT_BUILD_TYPE={build_type:?} isn't a CMake build type, \
+             use one of {BUILD_TYPES:?}"
+        );
+        // Otherwise the cmake crate picks based on the cargo profile.
+        cmake_build.profile(&build_type);
+    }
+
+    configure_sanitizers(&mut cmake_build, sanitizers);
+
+    stdlib.configure(&mut cmake_build);
 
     // https://github.com/aDotInTheVoid/boxcars/issues/1#issuecomment-1812070337
     cmake_build.define("VERONA_RT_ONLY_HEADER_LIBRARY", "ON");
 
-    let dst = cmake_build.build();
+    cmake_build.build()
+}
 
-    println!("cargo:rustc-link-search=native={}", dst.display());
-    println!("cargo:rustc-link-lib=static=boxcar_bindings");
+/// The C++ standard library to build and link against, chosen by the
+/// `VERONA_RT_CXX_STDLIB` environment variable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CxxStdlib {
+    /// GCC's. The default.
+    LibStdCxx,
+    /// LLVM's. Needs clang.
+    LibCxx,
+}
 
-    // This is GCC's.
-    println!("cargo:rustc-link-lib=stdc++");
-    println!("cargo:rustc-link-lib=atomic");
+impl CxxStdlib {
+    fn from_env() -> Self {
+        println!("cargo:rerun-if-env-changed=VERONA_RT_CXX_STDLIB");
 
-    // This is LLVM's
-    // println!("cargo:rustc-link-lib=static=c++");
+        let stdlib = match env::var("VERONA_RT_CXX_STDLIB").as_deref() {
+            Err(_) | Ok("libstdc++") => CxxStdlib::LibStdCxx,
+            Ok("libc++") => CxxStdlib::LibCxx,
+            Ok(other) => {
+                panic!("unknown VERONA_RT_CXX_STDLIB={other:?}, expected `libstdc++` or `libc++`")
+            }
+        };
+
+        // So the tests can check we got what we asked for.
+        println!("cargo:rustc-env=BOXCAR_CXX_STDLIB={}", stdlib.name());
+
+        stdlib
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            CxxStdlib::LibStdCxx => "libstdc++",
+            CxxStdlib::LibCxx => "libc++",
+        }
+    }
+
+    fn configure(self, cmake_build: &mut cmake::Config) {
+        match self {
+            CxxStdlib::LibStdCxx => {}
+            CxxStdlib::LibCxx => {
+                cmake_build
+                    .define("CMAKE_C_COMPILER", "clang")
+                    .define("CMAKE_CXX_COMPILER", "clang++")
+                    .cxxflag("-stdlib=libc++");
+            }
+        }
+    }
+
+    fn link(self) {
+        match self {
+            CxxStdlib::LibStdCxx => {
+                println!("cargo:rustc-link-lib=stdc++");
+                // GCC doesn't inline 16 byte atomics.
+                println!("cargo:rustc-link-lib=atomic");
+            }
+            CxxStdlib::LibCxx => {
+                println!("cargo:rustc-link-lib=c++");
+                println!("cargo:rustc-link-lib=c++abi");
+            }
+        }
+    }
 }
 
-fn has_ninja() -> bool {
-    std::process::Command::new("ninja")
-        .arg("--version")
+/// Run the layout probe built alongside the bindings, and save its output for
+/// `src/lib.rs` to include.
+///
+/// This runs a binary built for the target, so doesn't work when cross
+/// compiling.
+fn write_layout(dst: &Path, stdlib: CxxStdlib, sanitizers: &[&str]) {
+    let probe = dst.join("boxcar_layout");
+    let output = Command::new(&probe)
+        .output()
+        .unwrap_or_else(|e| panic!("failed to run {}: {e}", probe.display()));
+    assert!(
+        output.status.success(),
+        "{} failed: {}",
+        probe.display(),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let layout = String::from_utf8(output.stdout).unwrap();
+    check_abi_version(&layout, dst);
+    check_build_config(&layout, dst, stdlib, sanitizers);
+
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    fs::write(out_dir.join("layout.rs"), layout).unwrap();
+}
+
+/// Check the bindings export the functions `src/raw.rs` expects.
+///
+/// This mostly matters for prebuilt libraries, which may be from an older
+/// version of this crate.
+fn check_abi_version(layout: &str, dst: &Path) {
+    let expected = expected_abi_version();
+    let abi_version = layout
+        .lines()
+        .find_map(|line| line.strip_prefix("pub const ABI_VERSION: usize = "))
+        .and_then(|v| v.strip_suffix(';')?.parse::<usize>().ok());
+
+    match abi_version {
+        Some(v) if v == expected => {}
+        Some(v) => panic!(
+            "verona-rt in {} has ABI version {v}, but this version of verona-rt-sys \
+             needs {expected}. Rebuild it from this crate's `cpp` directory.",
+            dst.display()
+        ),
+        None => panic!(
+            "verona-rt in {} doesn't report an ABI version, so is too old for this \
+             version of verona-rt-sys. Rebuild it from this crate's `cpp` directory.",
+            dst.display()
+        ),
+    }
+}
+
+/// Check the library was built the way the cargo features and environment
+/// ask for.
+///
+/// Like [`check_abi_version`], this is for prebuilt libraries, as build.rs
+/// configures the ones it builds itself.
+fn check_build_config(layout: &str, dst: &Path, stdlib: CxxStdlib, sanitizers: &[&str]) {
+    let expected = [
+        (
+            "CONFIG_SYSTEMATIC_TESTING",
+            cfg!(feature = "systematic_testing").to_string(),
+            "the systematic_testing feature",
+        ),
+        (
+            "CONFIG_FLIGHT_RECORDER",
+            cfg!(feature = "flight_recorder").to_string(),
+            "the flight_recorder feature",
+        ),
+        (
+            "CONFIG_CROSS_LANG_LTO",
+            cfg!(feature = "cross_lang_lto").to_string(),
+            "the cross_lang_lto feature",
+        ),
+        (
+            "CONFIG_SANITIZERS",
+            format!("{:?}", sanitizers.join(",")),
+            "the asan, tsan and ubsan features",
+        ),
+        (
+            "CONFIG_CXX_STDLIB",
+            format!("{:?}", stdlib.name()),
+            "VERONA_RT_CXX_STDLIB",
+        ),
+    ];
+
+    for (name, expected, source) in expected {
+        let actual = layout
+            .lines()
+            .find_map(|line| {
+                let line = line.strip_prefix("pub const ")?.strip_prefix(name)?;
+                line.strip_prefix(':')?.split_once(" = ")
+            })
+            .and_then(|(_, value)| value.strip_suffix(';'))
+            .unwrap_or_else(|| panic!("boxcar_layout in {} doesn't report {name}", dst.display()));
+        assert!(
+            actual == expected,
+            "verona-rt in {} was built with {name} = {actual}, but {source} asks \
+             for {expected}. Rebuild it with matching options (see cpp/README.md).",
+            dst.display()
+        );
+    }
+}
+
+/// The ABI version of the bindings in `cpp`, read from the header that
+/// defines it, so it can't get out of step with the C++.
+fn expected_abi_version() -> usize {
+    const HEADER: &str = "cpp/boxcar_types.h";
+    println!("cargo:rerun-if-changed={HEADER}");
+
+    let header =
+        fs::read_to_string(HEADER).unwrap_or_else(|e| panic!("failed to read {HEADER}: {e}"));
+    header
+        .lines()
+        .find_map(|line| {
+            let line = line.strip_prefix('#')?.trim_start();
+            line.strip_prefix("define BOXCAR_ABI_VERSION ")
+        })
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or_else(|| panic!("couldn't find the BOXCAR_ABI_VERSION define in {HEADER}"))
+}
+
+/// The sanitizers selected by cargo features, as named by `-fsanitize`. See
+/// docs/sanitizers.md.
+fn sanitizers() -> Vec<&'static str> {
+    let asan = cfg!(feature = "asan");
+    let tsan = cfg!(feature = "tsan");
+    let ubsan = cfg!(feature = "ubsan");
+
+    assert!(
+        !(asan && tsan),
+        "the asan and tsan features can't be used together"
+    );
+
+    // The sanitizer runtimes come from rustc, so it must be instrumenting
+    // Rust code too. Cargo sets this from `-Zsanitizer`.
+    let rust_sanitizers = env::var("CARGO_CFG_SANITIZE").unwrap_or_default();
+    for (enabled, name, feature) in [(asan, "address", "asan"), (tsan, "thread", "tsan")] {
+        if enabled {
+            assert!(
+                rust_sanitizers.split(',').any(|s| s == name),
+                "the {feature} feature needs Rust built with the same sanitizer. \
+                 Set RUSTFLAGS=-Zsanitizer={name} (see docs/sanitizers.md)"
+            );
+        }
+    }
+
+    [(asan, "address"), (tsan, "thread"), (ubsan, "undefined")]
+        .into_iter()
+        .filter_map(|(enabled, name)| enabled.then_some(name))
+        .collect()
+}
+
+/// Build with `sanitizers`.
+fn configure_sanitizers(cmake_build: &mut cmake::Config, sanitizers: &[&str]) {
+    let asan = sanitizers.contains(&"address");
+    let tsan = sanitizers.contains(&"thread");
+    let ubsan = sanitizers.contains(&"undefined");
+
+    // Reported by boxcar_layout.
+    cmake_build.define("BOXCAR_SANITIZERS", sanitizers.join(","));
+
+    for (enabled, name) in [(asan, "address"), (tsan, "thread")] {
+        if enabled {
+            cmake_build
+                .cflag(format!("-fsanitize={name}"))
+                .cxxflag(format!("-fsanitize={name}"));
+        }
+    }
+
+    if asan {
+        // Otherwise snmalloc hides allocations from ASan.
+        cmake_build.cxxflag("-DSNMALLOC_PASS_THROUGH");
+    }
+
+    if ubsan {
+        // Rust doesn't have a UBSan runtime to link, so trap instead.
+        cmake_build
+            .cxxflag("-fsanitize=undefined")
+            .cxxflag("-fsanitize-trap=undefined");
+    }
+
+    if asan || tsan || ubsan {
+        cmake_build.cxxflag("-fno-omit-frame-pointer");
+    }
+}
+
+/// Build the bindings as LLVM bitcode, so rustc can inline them into Rust
+/// code. See docs/cross_lang_lto.md.
+fn configure_cross_lang_lto(cmake_build: &mut cmake::Config) {
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_owned());
+    let rustc_llvm = llvm_major_version(&rustc, "-vV", "LLVM version: ");
+    let clang_llvm = llvm_major_version("clang", "--version", "clang version ");
+    assert_eq!(
+        rustc_llvm, clang_llvm,
+        "cross_lang_lto needs clang to use the same LLVM as rustc, \
+         but rustc uses LLVM {rustc_llvm} and clang uses LLVM {clang_llvm}"
+    );
+
+    cmake_build
+        .define("CMAKE_C_COMPILER", "clang")
+        .define("CMAKE_CXX_COMPILER", "clang++")
+        .define("BOXCAR_CROSS_LANG_LTO", "ON")
+        .cflag("-flto=thin")
+        .cxxflag("-flto=thin");
+}
+
+/// Check Rust is set up to link the bitcode built by `cross_lang_lto`.
+fn check_linker_plugin_lto() {
+    // The library is bitcode, which only a linker doing LTO can read, and the
+    // build script can't turn that on for the final binary.
+    let rustflags = env::var("CARGO_ENCODED_RUSTFLAGS").unwrap_or_default();
+    assert!(
+        rustflags.contains("linker-plugin-lto"),
+        "cross_lang_lto builds the bindings as LLVM bitcode, which won't link \
+         without linker plugin LTO. Set \
+         RUSTFLAGS=\"-Clinker-plugin-lto -Clinker=clang -Clink-arg=-fuse-ld=lld\" \
+         (see docs/cross_lang_lto.md)"
+    );
+}
+
+/// Find the major LLVM version in the output of `cmd arg`.
+fn llvm_major_version(cmd: &str, arg: &str, prefix: &str) -> u32 {
+    let output = Command::new(cmd)
+        .arg(arg)
         .output()
-        .is_ok()
+        .unwrap_or_else(|e| panic!("failed to run `{cmd} {arg}`: {e}"));
+    let output = String::from_utf8_lossy(&output.stdout);
+
+    output
+        .lines()
+        .find_map(|line| line.split_once(prefix))
+        .and_then(|(_, version)| version.split('.').next()?.parse().ok())
+        .unwrap_or_else(|| panic!("couldn't find the LLVM version in `{cmd} {arg}`"))
+}
+
+fn has_ninja() -> bool {
+    Command::new("ninja").arg("--version").output().is_ok()
 }
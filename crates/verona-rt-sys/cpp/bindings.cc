@@ -2,32 +2,44 @@
 #include <bit>
 #include <cstdint>
 #include <string_view>
+#include <vector>
 // verona
-#include <cpp/cown.h>
 #include <cpp/when.h>
 #include <sched/schedulerthread.h>
+// boxcars
+#include "boxcar_types.h"
 
 using verona::cpp::make_cown;
 using verona::cpp::when;
+using verona::rt::Behaviour;
+using verona::rt::BehaviourCore;
 using verona::rt::Scheduler;
+using verona::rt::Shared;
+using verona::rt::Slot;
 using verona::rt::VCown;
 
-using verona::cpp::DtorThunk;
-
-using cown_ptr = verona::cpp::cown_ptr<DtorThunk>;
-using acquired_cown = verona::cpp::acquired_cown<DtorThunk>;
-using ActualCown = verona::cpp::ActualCown<DtorThunk>;
-
-// Sane Rust platform assumptions.
-static_assert(sizeof(void*) == sizeof(size_t));
-static_assert(sizeof(void*) == sizeof(ptrdiff_t));
+// cown_ptr is a single pointer to the ActualCown (see docs/layout.md), but
+// doesn't expose it.
+static ActualCown* actual_cown(const cown_ptr* ptr)
+{
+  return *reinterpret_cast<ActualCown* const*>(ptr);
+}
 
-// Ensure we're right about the definition of cown_ptr/acquired_cown.
-static_assert(sizeof(cown_ptr) == sizeof(void*));
-static_assert(sizeof(acquired_cown) == sizeof(void*));
+/// The body of a behaviour scheduled by boxcar_schedule_many.
+///
+/// `acquired` is laid out like an array of acquired_cown, which is what
+/// Rust expects.
+struct BoxcarBody
+{
+  void (*func)(ActualCown** acquired, void* data);
+  void* data;
+  std::vector<ActualCown*> acquired;
 
-static constexpr size_t actual_sz = sizeof(ActualCown);
-static_assert(alignof(ActualCown) == alignof(void*));
+  void operator()()
+  {
+    func(acquired.data(), data);
+  }
+};
 
 // It's critical that you don't pass a cown_ptr or an actual_cown directly as an
 // argument, or use them in a return type, as that'll lead to the wrong ABI.
@@ -52,6 +64,13 @@ extern "C"
   {
     sched->run();
   }
+  /// Like `scheduler_run`, but each worker thread calls `startup(arg)` before
+  /// it starts running behaviours.
+  void scheduler_run_with_startup(
+    Scheduler* sched, void (*startup)(void*), void* arg)
+  {
+    sched->run_with_startup(startup, arg);
+  }
 
   void schedular_set_detect_leaks(bool detect_leaks)
   {
@@ -64,6 +83,27 @@ extern "C"
     return !is_ok;
   }
 
+  /// While there are external event sources, the scheduler keeps running even
+  /// when it has no work, as more may arrive from outside.
+  void boxcar_add_external_event_source()
+  {
+    Scheduler::add_external_event_source();
+  }
+  void boxcar_remove_external_event_source()
+  {
+    Scheduler::remove_external_event_source();
+  }
+
+  /// Only does anything when built with USE_SYSTEMATIC_TESTING.
+  void boxcar_set_seed(uint64_t seed)
+  {
+#ifdef USE_SYSTEMATIC_TESTING
+    Systematic::set_seed(seed);
+#else
+    (void)seed;
+#endif
+  }
+
   /*
    * Logging
    */
@@ -111,6 +151,11 @@ extern "C"
   {
     *out = ptr->cown();
   }
+  /// The raw strong reference count, for checking the Rust fast path.
+  size_t boxcar_cownptr_debug_rc(cown_ptr* ptr)
+  {
+    return actual_cown(ptr)->debug_rc();
+  }
 
   void boxcar_size_info(
     size_t* sizeof_actualcown,
@@ -138,6 +183,60 @@ extern "C"
     when(*c1, *c2) << [=](auto a1, auto a2) { func(&a1, &a2, data); };
   }
 
+  struct boxcar_behaviour
+  {
+    const cown_ptr* const* cowns;
+    size_t count;
+    void (*func)(ActualCown** acquired, void* data);
+    void* data;
+  };
+
+  /// Schedule all the behaviours in one step, so no other behaviour can be
+  /// ordered between them on any cown.
+  void boxcar_schedule_many(const boxcar_behaviour* behaviours, size_t count)
+  {
+    std::vector<BehaviourCore*> bodies;
+    bodies.reserve(count);
+
+    for (size_t i = 0; i < count; i++)
+    {
+      const boxcar_behaviour& b = behaviours[i];
+
+      BoxcarBody body{b.func, b.data, {}};
+      body.acquired.reserve(b.count);
+      for (size_t j = 0; j < b.count; j++)
+        body.acquired.push_back(actual_cown(b.cowns[j]));
+
+      BehaviourCore* core = Behaviour::make<BoxcarBody>(b.count, std::move(body));
+
+      // Like `when`, each slot holds a reference to its cown, which the
+      // runtime releases once the behaviour has run.
+      Slot* slots = core->get_slots();
+      for (size_t j = 0; j < b.count; j++)
+      {
+        ActualCown* cown = actual_cown(b.cowns[j]);
+        Shared::acquire(cown);
+        new (&slots[j]) Slot(cown);
+      }
+
+      bodies.push_back(core);
+    }
+
+    BehaviourCore::schedule_many(bodies.data(), bodies.size());
+  }
+
+  /// Which C++ standard library we were built against.
+  const char* boxcar_cxx_stdlib()
+  {
+#if defined(_LIBCPP_VERSION)
+    return "libc++";
+#elif defined(__GLIBCXX__)
+    return "libstdc++";
+#else
+    return "unknown";
+#endif
+  }
+
   int32_t boxcars_add(int32_t a, int32_t b)
   {
     return a + b;
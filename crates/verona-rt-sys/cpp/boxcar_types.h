@@ -0,0 +1,32 @@
+#pragma once
+
+// Types shared between the bindings and the layout probe.
+
+#include <cpp/cown.h>
+
+// Bump whenever a function in bindings.cc is added, removed or changed, or
+// boxcar_layout reports something new, so a prebuilt library from a different
+// version is rejected. build.rs reads the expected version from the define
+// below.
+//
+// Only overridden by CI, to check mismatches are reported.
+#ifndef BOXCAR_ABI_VERSION
+#  define BOXCAR_ABI_VERSION 4
+#endif
+
+using verona::cpp::DtorThunk;
+
+using cown_ptr = verona::cpp::cown_ptr<DtorThunk>;
+using acquired_cown = verona::cpp::acquired_cown<DtorThunk>;
+using ActualCown = verona::cpp::ActualCown<DtorThunk>;
+
+// Sane Rust platform assumptions.
+static_assert(sizeof(void*) == sizeof(size_t));
+static_assert(sizeof(void*) == sizeof(ptrdiff_t));
+
+// Ensure we're right about the definition of cown_ptr/acquired_cown.
+static_assert(sizeof(cown_ptr) == sizeof(void*));
+static_assert(sizeof(acquired_cown) == sizeof(void*));
+
+static constexpr size_t actual_sz = sizeof(ActualCown);
+static_assert(alignof(ActualCown) == alignof(void*));
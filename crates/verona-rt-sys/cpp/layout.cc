@@ -0,0 +1,61 @@
+// Prints the layout of the C++ types that Rust needs to know about, and how
+// the bindings were built, as Rust source. Run by build.rs, which checks the
+// build configuration and includes the output in the sys crate.
+
+// std
+#include <cstddef>
+#include <cstdio>
+// boxcars
+#include "boxcar_types.h"
+
+static void
+emit(const char* name, const char* cpp_expr, size_t value)
+{
+  std::printf("/// `%s`\n", cpp_expr);
+  std::printf("pub const %s: usize = %zu;\n", name, value);
+}
+
+static void
+emit_flag(const char* name, bool value)
+{
+  std::printf("/// How the bindings were built.\n");
+  std::printf("pub const %s: bool = %s;\n", name, value ? "true" : "false");
+}
+
+static void
+emit_string(const char* name, const char* value)
+{
+  std::printf("/// How the bindings were built.\n");
+  std::printf("pub const %s: &str = \"%s\";\n", name, value);
+}
+
+int main()
+{
+  emit("ABI_VERSION", "BOXCAR_ABI_VERSION", BOXCAR_ABI_VERSION);
+  emit("SIZEOF_ACTUALCOWN", "sizeof(ActualCown)", sizeof(ActualCown));
+  emit("ALIGNOF_ACTUALCOWN", "alignof(ActualCown)", alignof(ActualCown));
+  emit(
+    "SIZEOF_OBJECT_HEADER",
+    "sizeof(verona::rt::Object::Header)",
+    sizeof(verona::rt::Object::Header));
+  emit(
+    "OBJECT_ALIGNMENT",
+    "verona::rt::Object::ALIGNMENT",
+    verona::rt::Object::ALIGNMENT);
+  emit(
+    "OBJECT_RC_OFFSET",
+    "offsetof(verona::rt::Object::Header, rc)",
+    offsetof(verona::rt::Object::Header, rc));
+  emit("OBJECT_ONE_RC", "verona::rt::Object::ONE_RC", verona::rt::Object::ONE_RC);
+
+  emit_flag("CONFIG_SYSTEMATIC_TESTING", BOXCAR_SYSTEMATIC_TESTING);
+  emit_flag("CONFIG_FLIGHT_RECORDER", BOXCAR_FLIGHT_RECORDER);
+  emit_flag("CONFIG_CROSS_LANG_LTO", BOXCAR_CROSS_LANG_LTO);
+  emit_string("CONFIG_SANITIZERS", BOXCAR_SANITIZERS);
+#ifdef _LIBCPP_VERSION
+  emit_string("CONFIG_CXX_STDLIB", "libc++");
+#else
+  emit_string("CONFIG_CXX_STDLIB", "libstdc++");
+#endif
+  return 0;
+}
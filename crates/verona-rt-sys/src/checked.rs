@@ -0,0 +1,501 @@
+//! Wrappers over [`raw`] that document and check their invariants.
+//!
+//! The scheduler's state (uninitialized, initialized, or running) is tracked
+//! here, so in debug builds calls made in the wrong state return a
+//! [`SchedulerError`] instead of crashing somewhere in C++. Release builds
+//! skip the checks, and trust the caller.
+//!
+//! Functions that take pointers to cowns are still `unsafe`, as there's no
+//! way to check that a cown is alive.
+
+use core::{
+    ffi::CStr,
+    fmt, mem,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use crate::{
+    layout::{SIZEOF_ACTUALCOWN, SIZEOF_OBJECT_HEADER},
+    raw, AcquiredCown, BatchBehaviour, CownPtr, Dtor, Scheduler,
+};
+
+/// A precondition of a call into the runtime wasn't met.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SchedulerError {
+    /// The scheduler was asked to run with zero threads.
+    ZeroThreads,
+    /// The scheduler was initialized when it already had been.
+    AlreadyInitialized,
+    /// The operation needs the scheduler to be initialized, but it isn't.
+    NotInitialized,
+    /// The operation can't happen while the scheduler is running.
+    AlreadyRunning,
+}
+
+impl fmt::Display for SchedulerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SchedulerError::ZeroThreads => "scheduler needs at least one thread",
+            SchedulerError::AlreadyInitialized => "scheduler is already initialized",
+            SchedulerError::NotInitialized => "scheduler isn't initialized",
+            SchedulerError::AlreadyRunning => "scheduler is already running",
+        })
+    }
+}
+
+impl core::error::Error for SchedulerError {}
+
+/// Where the global scheduler is in its lifecycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SchedulerState {
+    /// Before [`init`], or after [`run`] has returned.
+    Uninitialized = 0,
+    /// After [`init`], but before [`run`]. Work can be scheduled, but won't
+    /// run yet.
+    Initialized = 1,
+    /// Inside [`run`].
+    Running = 2,
+}
+
+static STATE: AtomicU8 = AtomicU8::new(SchedulerState::Uninitialized as u8);
+
+/// The current state of the scheduler.
+pub fn state() -> SchedulerState {
+    match STATE.load(Ordering::SeqCst) {
+        0 => SchedulerState::Uninitialized,
+        1 => SchedulerState::Initialized,
+        2 => SchedulerState::Running,
+        _ => unreachable!(),
+    }
+}
+
+fn set_state(state: SchedulerState) {
+    STATE.store(state as u8, Ordering::SeqCst);
+}
+
+/// Returns `Err(err)` if `ok` is false, in debug builds.
+fn check(ok: bool, err: SchedulerError) -> Result<(), SchedulerError> {
+    if cfg!(debug_assertions) && !ok {
+        Err(err)
+    } else {
+        Ok(())
+    }
+}
+
+/// Check that we're inside a session, so cowns and behaviours can be created.
+fn check_in_session() -> Result<(), SchedulerError> {
+    check(
+        state() != SchedulerState::Uninitialized,
+        SchedulerError::NotInitialized,
+    )
+}
+
+/// Returns the global `Scheduler`.
+pub fn scheduler() -> Scheduler {
+    // SAFETY: Always safe.
+    unsafe { raw::scheduler_get() }
+}
+
+/// Initialize the scheduler with `n_threads` worker threads.
+///
+/// ## Errors
+///
+/// - [`SchedulerError::ZeroThreads`] if `n_threads` is zero.
+/// - [`SchedulerError::AlreadyInitialized`] or
+///   [`SchedulerError::AlreadyRunning`] if the scheduler isn't
+///   [`SchedulerState::Uninitialized`].
+pub fn init(n_threads: usize) -> Result<(), SchedulerError> {
+    check(n_threads != 0, SchedulerError::ZeroThreads)?;
+    match state() {
+        SchedulerState::Uninitialized => {}
+        SchedulerState::Initialized => check(false, SchedulerError::AlreadyInitialized)?,
+        SchedulerState::Running => check(false, SchedulerError::AlreadyRunning)?,
+    }
+
+    // SAFETY: Checked above.
+    unsafe { raw::scheduler_init(scheduler(), n_threads) };
+    set_state(SchedulerState::Initialized);
+    Ok(())
+}
+
+/// Run the scheduler until all behaviours have finished.
+///
+/// Once this returns, the scheduler is uninitialized again.
+///
+/// ## Errors
+///
+/// - [`SchedulerError::NotInitialized`] if [`init`] hasn't been called.
+/// - [`SchedulerError::AlreadyRunning`] if the scheduler is already running.
+pub fn run() -> Result<(), SchedulerError> {
+    match state() {
+        SchedulerState::Initialized => {}
+        SchedulerState::Uninitialized => check(false, SchedulerError::NotInitialized)?,
+        SchedulerState::Running => check(false, SchedulerError::AlreadyRunning)?,
+    }
+
+    set_state(SchedulerState::Running);
+    // SAFETY: Checked above.
+    unsafe { raw::scheduler_run(scheduler()) };
+    set_state(SchedulerState::Uninitialized);
+    Ok(())
+}
+
+/// Like [`run`], but each worker thread calls `startup(arg)` before it runs
+/// any behaviours.
+///
+/// ## Errors
+///
+/// As for [`run`].
+///
+/// ## Safety
+///
+/// - `startup` must be safe to call with `arg` from every worker thread at
+///   once, and must not unwind.
+pub unsafe fn run_with_startup(
+    startup: extern "C" fn(*mut ()),
+    arg: *mut (),
+) -> Result<(), SchedulerError> {
+    match state() {
+        SchedulerState::Initialized => {}
+        SchedulerState::Uninitialized => check(false, SchedulerError::NotInitialized)?,
+        SchedulerState::Running => check(false, SchedulerError::AlreadyRunning)?,
+    }
+
+    set_state(SchedulerState::Running);
+    // SAFETY: State checked above, and the caller upholds the rest.
+    unsafe { raw::scheduler_run_with_startup(scheduler(), startup, arg) };
+    set_state(SchedulerState::Uninitialized);
+    Ok(())
+}
+
+/// Set whether the scheduler checks for leaks when it finishes running.
+///
+/// ## Errors
+///
+/// - [`SchedulerError::AlreadyRunning`] if the scheduler is running.
+pub fn set_detect_leaks(detect_leaks: bool) -> Result<(), SchedulerError> {
+    check(
+        state() != SchedulerState::Running,
+        SchedulerError::AlreadyRunning,
+    )?;
+
+    // SAFETY: Checked above.
+    unsafe { raw::schedular_set_detect_leaks(detect_leaks) };
+    Ok(())
+}
+
+/// Returns if any memory is still allocated.
+///
+/// Only meaningful once the scheduler has finished running.
+pub fn has_leaks() -> bool {
+    // SAFETY: Always safe.
+    unsafe { raw::schedular_has_leaks() }
+}
+
+/// Keep the scheduler running, even with no work, until a matching
+/// [`remove_external_event_source`]. This lets other threads schedule
+/// behaviours while it runs.
+///
+/// ## Errors
+///
+/// - [`SchedulerError::NotInitialized`] outside of a scheduler session.
+pub fn add_external_event_source() -> Result<(), SchedulerError> {
+    check_in_session()?;
+
+    // SAFETY: Checked above.
+    unsafe { raw::boxcar_add_external_event_source() };
+    Ok(())
+}
+
+/// Let the scheduler finish once it runs out of work again.
+///
+/// ## Safety
+///
+/// - Must be paired with an earlier [`add_external_event_source`] in the
+///   same session.
+pub unsafe fn remove_external_event_source() {
+    debug_assert_ne!(state(), SchedulerState::Uninitialized);
+    raw::boxcar_remove_external_event_source();
+}
+
+/// Set the seed used to pick interleavings under systematic testing.
+///
+/// Does nothing unless built with the `systematic_testing` feature.
+///
+/// ## Errors
+///
+/// - [`SchedulerError::AlreadyInitialized`] or
+///   [`SchedulerError::AlreadyRunning`] if the scheduler isn't
+///   [`SchedulerState::Uninitialized`], as the seed wouldn't apply.
+pub fn set_seed(seed: u64) -> Result<(), SchedulerError> {
+    match state() {
+        SchedulerState::Uninitialized => {}
+        SchedulerState::Initialized => check(false, SchedulerError::AlreadyInitialized)?,
+        SchedulerState::Running => check(false, SchedulerError::AlreadyRunning)?,
+    }
+
+    // SAFETY: Checked above.
+    unsafe { raw::boxcar_set_seed(seed) };
+    Ok(())
+}
+
+/// Allocate a new cown of `size` bytes, which will call `dtor` when its
+/// reference count reaches zero.
+///
+/// The memory after the `ActualCown` is uninitialized, and is the callers
+/// responsibility.
+///
+/// ## Errors
+///
+/// - [`SchedulerError::NotInitialized`] outside of a scheduler session.
+///
+/// ## Panics
+///
+/// If `size` is too small to hold the cown and its object header.
+pub fn cownptr_new(size: usize, dtor: Dtor) -> Result<CownPtr, SchedulerError> {
+    check_in_session()?;
+    assert!(
+        size >= SIZEOF_ACTUALCOWN + SIZEOF_OBJECT_HEADER,
+        "cown allocation of {size} bytes is too small"
+    );
+
+    // SAFETY: The C++ code will read from the old value of cown to attempt
+    // to free it. `nullptr` is a valid value for a cown_ptr, so that's fine.
+    unsafe {
+        let mut cown_ptr = mem::zeroed();
+        raw::boxcar_cownptr_new(size, dtor, &mut cown_ptr);
+        Ok(cown_ptr)
+    }
+}
+
+/// Create a new reference to the same cown.
+///
+/// ## Safety
+///
+/// - `cown` must be a live cown.
+pub unsafe fn cownptr_clone(cown: &CownPtr) -> CownPtr {
+    debug_assert!(!cown.addr().is_null());
+
+    let mut new = mem::zeroed();
+    raw::boxcar_cownptr_clone(cown, &mut new);
+    new
+}
+
+/// Release a reference to a cown.
+///
+/// ## Safety
+///
+/// - `cown` must be a live cown.
+/// - `cown` must not be used afterwards.
+pub unsafe fn cownptr_drop(cown: &mut CownPtr) {
+    debug_assert!(!cown.addr().is_null());
+
+    raw::boxcar_cownptr_drop(cown)
+}
+
+/// The raw value of a cown's strong reference count, as seen by C++.
+///
+/// This is in units of [`OBJECT_ONE_RC`](crate::layout::OBJECT_ONE_RC), and
+/// is only useful for debugging.
+///
+/// ## Safety
+///
+/// - `cown` must be a live cown.
+pub unsafe fn cownptr_debug_rc(cown: &CownPtr) -> usize {
+    debug_assert!(!cown.addr().is_null());
+
+    raw::boxcar_cownptr_debug_rc(cown)
+}
+
+/// Create a new reference to an acquired cown.
+///
+/// ## Safety
+///
+/// - `acquired` must be from a behaviour that's currently running.
+pub unsafe fn acquiredcown_cown(acquired: &AcquiredCown) -> CownPtr {
+    let mut out = mem::zeroed();
+    raw::boxcar_acquiredcown_cown(acquired, &mut out);
+    out
+}
+
+/// Sizes of C++ types, as seen by the library we linked against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeInfo {
+    pub sizeof_actualcown: usize,
+    pub alignof_actualcown: usize,
+    pub sizeof_object_header: usize,
+    pub object_alignment: usize,
+}
+
+pub fn size_info() -> SizeInfo {
+    let mut info = SizeInfo {
+        sizeof_actualcown: 0,
+        alignof_actualcown: 0,
+        sizeof_object_header: 0,
+        object_alignment: 0,
+    };
+
+    // SAFETY: Only writes to the out-params.
+    unsafe {
+        raw::boxcar_size_info(
+            &mut info.sizeof_actualcown,
+            &mut info.alignof_actualcown,
+            &mut info.sizeof_object_header,
+            &mut info.object_alignment,
+        )
+    };
+
+    info
+}
+
+/// Schedule `func` to run with `cown` acquired.
+///
+/// ## Errors
+///
+/// - [`SchedulerError::NotInitialized`] outside of a scheduler session.
+///
+/// ## Safety
+///
+/// - `cown` must be a live cown.
+/// - `func` must be safe to call with `data` on any thread, at any later time.
+pub unsafe fn when1(
+    cown: &CownPtr,
+    func: extern "C" fn(&mut AcquiredCown, *mut ()),
+    data: *mut (),
+) -> Result<(), SchedulerError> {
+    check_in_session()?;
+
+    raw::boxcar_when1(cown, func, data);
+    Ok(())
+}
+
+/// Schedule `func` to run with `c1` and `c2` acquired.
+///
+/// ## Errors
+///
+/// - [`SchedulerError::NotInitialized`] outside of a scheduler session.
+///
+/// ## Safety
+///
+/// - `c1` and `c2` must be live cowns.
+/// - `c1` and `c2` must be different cowns.
+/// - `func` must be safe to call with `data` on any thread, at any later time.
+pub unsafe fn when2(
+    c1: &CownPtr,
+    c2: &CownPtr,
+    func: extern "C" fn(&mut AcquiredCown, &mut AcquiredCown, *mut ()),
+    data: *mut (),
+) -> Result<(), SchedulerError> {
+    check_in_session()?;
+    debug_assert_ne!(c1.addr(), c2.addr(), "used the same cown twice");
+
+    raw::boxcar_when2(c1, c2, func, data);
+    Ok(())
+}
+
+/// Schedule several behaviours atomically: no other behaviour can be ordered
+/// between them on any cown.
+///
+/// ## Errors
+///
+/// - [`SchedulerError::NotInitialized`] outside of a scheduler session.
+///
+/// ## Safety
+///
+/// - Each [`BatchBehaviour`] must point to `count` different, live, cowns.
+/// - Each `func` must be safe to call with its `data` on any thread, at any
+///   later time.
+pub unsafe fn schedule_many(behaviours: &[BatchBehaviour]) -> Result<(), SchedulerError> {
+    check_in_session()?;
+
+    raw::boxcar_schedule_many(behaviours.as_ptr(), behaviours.len());
+    Ok(())
+}
+
+/// The C++ standard library the runtime was built against, either
+/// `"libstdc++"` or `"libc++"`.
+///
+/// This is selected with the `VERONA_RT_CXX_STDLIB` environment variable at
+/// build time.
+pub fn cxx_stdlib() -> &'static str {
+    // SAFETY: Returns a static string.
+    let name = unsafe { CStr::from_ptr(raw::boxcar_cxx_stdlib()) };
+    name.to_str().unwrap()
+}
+
+/// Turn on the runtime's logging.
+///
+/// This is global, and can't be turned off again.
+pub fn enable_logging() {
+    // SAFETY: Always safe, but racy with anything that's currently logging.
+    unsafe { raw::enable_logging() }
+}
+
+/// Print the flight recorder's log.
+///
+/// Only does anything with the `flight_recorder` feature.
+pub fn dump_flight_recorder() {
+    // SAFETY: Always safe.
+    unsafe { raw::dump_flight_recorder() }
+}
+
+pub fn log_cstr(s: &CStr) {
+    // SAFETY: `s` is a valid, nul terminated, string.
+    unsafe { raw::boxcar_log_cstr(s.as_ptr()) }
+}
+
+pub fn log_usize(n: usize) {
+    // SAFETY: Always safe.
+    unsafe { raw::boxcar_log_usize(n) }
+}
+
+// The pointer is only printed, never dereferenced.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub fn log_ptr(p: *const ()) {
+    // SAFETY: See above.
+    unsafe { raw::boxcar_log_ptr(p) }
+}
+
+pub fn log_endl() {
+    // SAFETY: Always safe.
+    unsafe { raw::boxcar_log_endl() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cxx_stdlib_is_selected() {
+        assert_eq!(cxx_stdlib(), env!("BOXCAR_CXX_STDLIB"));
+    }
+
+    // None of the following get as far as C++, and the scheduler is never
+    // initialized in this crate's tests.
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn init_zero_threads() {
+        assert_eq!(init(0), Err(SchedulerError::ZeroThreads));
+        assert_eq!(state(), SchedulerState::Uninitialized);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn run_uninitialized() {
+        assert_eq!(run(), Err(SchedulerError::NotInitialized));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn cown_outside_session() {
+        extern "C" fn dtor(_: *mut ()) {}
+
+        assert_eq!(
+            cownptr_new(SIZEOF_ACTUALCOWN + SIZEOF_OBJECT_HEADER, dtor).err(),
+            Some(SchedulerError::NotInitialized)
+        );
+    }
+}
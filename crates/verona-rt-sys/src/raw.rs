@@ -0,0 +1,294 @@
+//! Raw declarations of the functions in `cpp/bindings.cc`.
+//!
+//! These are unchecked, and most are unsafe. Prefer the [`checked`](crate::checked)
+//! layer, which validates what it can.
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+/// A reference to a `verona::rt::Scheduler`.
+///
+/// The underlying Schedular is a singleton, but multiple `Schedular`
+/// structs can exist, which will all point to the same singleton.
+///
+/// Create with [`scheduler_get`]
+pub struct Scheduler(*mut ());
+
+#[repr(C)]
+/// This is a reference cointed pointer, so embeders shouldn't
+/// implement Copy.
+///
+/// Must not be moved directly over the FFI boundry, as C++ and rust
+/// use different calling conventions.
+pub struct CownPtr(*mut ());
+
+impl CownPtr {
+    pub fn addr(&self) -> *mut () {
+        self.0
+    }
+}
+impl AcquiredCown {
+    pub fn addr(&self) -> *mut () {
+        self.0
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct AcquiredCown(*mut ());
+
+pub type Dtor = extern "C" fn(*mut ());
+
+/// One behaviour in a call to [`boxcar_schedule_many`].
+#[repr(C)]
+pub struct BatchBehaviour {
+    /// The cowns to acquire. Must all be different.
+    pub cowns: *const *const CownPtr,
+    pub count: usize,
+    /// Called with an array of `count` acquired cowns, in the same order as
+    /// `cowns`.
+    pub func: extern "C" fn(*mut AcquiredCown, *mut ()),
+    pub data: *mut (),
+}
+
+#[link(name = "boxcar_bindings")]
+extern "C" {
+    #[cfg(test)]
+    /// Calculates a+b. Used for testing purposed only.
+    fn boxcars_add(a: i32, b: i32) -> i32;
+
+    /// Returns the global `Scheduler`.
+    ///
+    /// ## Safety
+    ///
+    /// Safe
+    pub fn scheduler_get() -> Scheduler;
+
+    /// Initialize the schedular with the given number of threads.
+    ///
+    /// ## Safety
+    ///
+    /// - `n_threads` must not be zero.
+    /// - The scheduler must not be initialized: either this has never been
+    ///   called, or [`scheduler_run`] has returned since it last was.
+    pub fn scheduler_init(schedular: Scheduler, n_threads: usize);
+
+    /// Run the schedular.
+    ///
+    /// Blocks until every behaviour has run, and then "de-initializes" the
+    /// scheduler, allowing you to "re-initialize" it.
+    ///
+    /// ## Safety
+    ///
+    /// - The scheduler must be initialized with [`scheduler_init`].
+    /// - The scheduler must not already be running.
+    pub fn scheduler_run(schedular: Scheduler);
+
+    /// Like [`scheduler_run`], but each worker thread calls `startup(arg)`
+    /// before running any behaviours.
+    ///
+    /// ## Safety
+    ///
+    /// - As for [`scheduler_run`].
+    /// - `startup` must be safe to call with `arg` from every worker thread
+    ///   at once, and must not unwind.
+    pub fn scheduler_run_with_startup(
+        schedular: Scheduler,
+        startup: extern "C" fn(*mut ()),
+        arg: *mut (),
+    );
+
+    /// Set whether the scheduler checks for leaks when it finishes running.
+    ///
+    /// ## Safety
+    ///
+    /// - This is a global flag, read without synchronization, so must not be
+    ///   called while the scheduler is running.
+    pub fn schedular_set_detect_leaks(detect_leaks: bool);
+
+    /// Returns if any memory is still allocated.
+    ///
+    /// ## Safety
+    ///
+    /// Safe, but only meaningful once the scheduler has finished running.
+    pub fn schedular_has_leaks() -> bool;
+
+    /// Stop [`scheduler_run`] from returning, even if there's no work, until
+    /// a matching [`boxcar_remove_external_event_source`].
+    ///
+    /// ## Safety
+    ///
+    /// - Must be called inside a schedular session.
+    pub fn boxcar_add_external_event_source();
+
+    /// Undo one [`boxcar_add_external_event_source`].
+    ///
+    /// ## Safety
+    ///
+    /// - Must be paired with an earlier [`boxcar_add_external_event_source`]
+    ///   in the same session.
+    pub fn boxcar_remove_external_event_source();
+
+    /// Set the seed used to pick interleavings under systematic testing.
+    ///
+    /// Does nothing unless built with the `systematic_testing` feature.
+    ///
+    /// ## Safety
+    ///
+    /// - Must be called before [`scheduler_init`] for the seed to apply to
+    ///   that session.
+    pub fn boxcar_set_seed(seed: u64);
+
+    /// Write a new reference to the cown `input` points to into `output`,
+    /// incrementing its reference count.
+    ///
+    /// ## Safety
+    ///
+    /// - `input` must be a live cown.
+    /// - `output` is released before being overwritten, so must be null, or
+    ///   a live cown.
+    pub fn boxcar_cownptr_clone(input: &CownPtr, output: &mut CownPtr);
+
+    /// Release the reference in `ptr`, freeing the cown (and calling its
+    /// `dtor`) if it was the last.
+    ///
+    /// ## Safety
+    ///
+    /// - `ptr` must be a live cown.
+    /// - `ptr` must not be used afterwards.
+    pub fn boxcar_cownptr_drop(ptr: &mut CownPtr);
+
+    /// Allocate a cown of `size` bytes, and write the only reference to it
+    /// into `output`.
+    ///
+    /// The bytes after the `ActualCown` are uninitialized.
+    ///
+    /// ## Safety
+    ///
+    /// - Must be called inside a schedular session.
+    /// - `size` must be at least `SIZEOF_ACTUALCOWN + SIZEOF_OBJECT_HEADER`.
+    /// - `dtor` must be safe to call with the cown's address on any thread,
+    ///   once, when the last reference is released.
+    /// - `output` is released before being overwritten, so must be null, or
+    ///   a live cown.
+    pub fn boxcar_cownptr_new(size: usize, dtor: Dtor, output: &mut CownPtr);
+
+    /// Write a new reference to the cown behind `input` into `out`.
+    ///
+    /// ## Safety
+    ///
+    /// - `input` must be from a behaviour that's currently running.
+    /// - `out` is released before being overwritten, as for
+    ///   [`boxcar_cownptr_clone`].
+    pub fn boxcar_acquiredcown_cown(input: &AcquiredCown, out: &mut CownPtr);
+
+    /// The raw value of the cown's strong reference count, for debugging.
+    ///
+    /// ## Safety
+    ///
+    /// - `ptr` must be a live cown.
+    pub fn boxcar_cownptr_debug_rc(ptr: &CownPtr) -> usize;
+
+    /// Write the sizes of C++ types, as compiled into this library.
+    ///
+    /// ## Safety
+    ///
+    /// Safe: only writes to the out-params.
+    pub fn boxcar_size_info(
+        sizeof_actualcown: &mut usize,
+        alignof_actualcown: &mut usize,
+        sizeof_object_header: &mut usize,
+        object_alignment: &mut usize,
+    );
+
+    /// Schedule `func` to run with `cown` acquired, passing it `data`.
+    ///
+    /// ## Safety
+    ///
+    /// - Must be called inside a schedular session.
+    /// - `cown` must be a live cown.
+    /// - `func` must be safe to call with `data` on any thread, at any later
+    ///   time, and must not unwind.
+    pub fn boxcar_when1(
+        cown: &CownPtr,
+        func: extern "C" fn(&mut AcquiredCown, *mut ()),
+        data: *mut (),
+    );
+    /// Schedule `func` to run with `c1` and `c2` acquired, passing it `data`.
+    ///
+    /// ## Safety
+    ///
+    /// - As for [`boxcar_when1`].
+    /// - `c1` and `c2` must be different cowns.
+    pub fn boxcar_when2(
+        c1: &CownPtr,
+        c2: &CownPtr,
+        func: extern "C" fn(&mut AcquiredCown, &mut AcquiredCown, *mut ()),
+        data: *mut (),
+    );
+
+    /// Schedule several behaviours atomically: no other behaviour can be
+    /// ordered between them on any cown.
+    ///
+    /// ## Safety
+    ///
+    /// - `behaviours` must point to `count` valid [`BatchBehaviour`]s.
+    /// - Must be called inside a schedular session.
+    pub fn boxcar_schedule_many(behaviours: *const BatchBehaviour, count: usize);
+
+    /// Returns a static, nul terminated, string naming the C++ standard
+    /// library: `"libstdc++"`, `"libc++"` or `"unknown"`.
+    ///
+    /// ## Safety
+    ///
+    /// Safe.
+    pub fn boxcar_cxx_stdlib() -> *const core::ffi::c_char;
+
+    /// Turn on the runtime's logging. This can't be undone.
+    ///
+    /// ## Safety
+    ///
+    /// Safe, but racy with anything that's currently logging.
+    pub fn enable_logging();
+
+    /// Print the flight recorder's log.
+    ///
+    /// ## Safety
+    ///
+    /// Safe. Does nothing unless built with the `flight_recorder` feature.
+    pub fn dump_flight_recorder();
+
+    /// Append a string to this thread's current log line.
+    ///
+    /// ## Safety
+    ///
+    /// - `ptr` must point to a valid, nul terminated, string.
+    pub fn boxcar_log_cstr(ptr: *const core::ffi::c_char);
+
+    /// Append a number to this thread's current log line.
+    ///
+    /// ## Safety
+    ///
+    /// Safe.
+    pub fn boxcar_log_usize(n: usize);
+
+    /// Append a pointer to this thread's current log line.
+    ///
+    /// ## Safety
+    ///
+    /// Safe: `p` is only printed, never dereferenced.
+    pub fn boxcar_log_ptr(p: *const ());
+
+    /// Finish this thread's current log line.
+    ///
+    /// ## Safety
+    ///
+    /// Safe.
+    pub fn boxcar_log_endl();
+}
+
+#[test]
+fn add_ints() {
+    unsafe {
+        assert_eq!(boxcars_add(1, 2), 3);
+    }
+}
@@ -0,0 +1,43 @@
+//! Cost of the operations that call into C++ on every use of a cown.
+//!
+//! Compare a normal build against one with cross language LTO (see
+//! docs/cross_lang_lto.md):
+//!
+//! ```text
+//! cargo bench -p verona-rt --bench refcount
+//! RUSTFLAGS="-Clinker-plugin-lto -Clinker=clang -Clink-arg=-fuse-ld=lld" \
+//!     cargo bench -p verona-rt --bench refcount --features cross_lang_lto
+//! ```
+
+use std::{hint::black_box, time::Instant};
+
+use verona_rt::{when, with_scheduler, CownPtr};
+
+const ITERS: u32 = 1_000_000;
+
+fn bench(name: &str, iters: u32, mut f: impl FnMut()) {
+    let start = Instant::now();
+    for _ in 0..iters {
+        f();
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{name:<12} {:>8.2} ns/iter",
+        elapsed.as_nanos() as f64 / iters as f64
+    );
+}
+
+fn main() {
+    with_scheduler(|| {
+        let cown = CownPtr::new(0u64);
+
+        bench("clone+drop", ITERS, || drop(black_box(cown.clone())));
+        bench("new+drop", ITERS, || drop(black_box(CownPtr::new(0u64))));
+
+        // These only get run once the closure returns, so this measures
+        // scheduling.
+        bench("when", ITERS / 10, || {
+            when(&cown, |mut c| *c += 1);
+        });
+    });
+}
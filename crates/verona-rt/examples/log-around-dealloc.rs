@@ -1,13 +1,10 @@
 use cstr::cstr;
-use verona_rt_sys as ffi;
+use verona_rt_sys::checked;
 
 use verona_rt::{log, with_scheduler, CownPtr};
 
 fn main() {
-    unsafe {
-        // SAFETY: No other work done yet.
-        ffi::enable_logging();
-    }
+    checked::enable_logging();
 
     with_scheduler(|| {
         log(cstr!("TOP"));
@@ -0,0 +1,115 @@
+//! Pinning worker threads to CPU cores, on Linux.
+//!
+//! Each worker pins itself as it starts, before running any behaviours, so
+//! this goes through the same startup function as
+//! [`SchedulerBuilder::on_thread_start`](crate::SchedulerBuilder::on_thread_start).
+
+use std::{io, mem};
+
+/// Which cores the scheduler's worker threads run on.
+///
+/// Set with [`SchedulerBuilder::affinity`](crate::SchedulerBuilder::affinity).
+/// Workers are pinned in the order they start, as given by
+/// [`WorkerId`](crate::WorkerId), wrapping round if there are more workers
+/// than cores.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Affinity {
+    /// Pin worker `i` to `cores[i % cores.len()]`.
+    ///
+    /// To keep the runtime on one NUMA node, list that node's cores, from
+    /// `/sys/devices/system/node/node<N>/cpulist`.
+    Cores(Vec<usize>),
+    /// Pin each worker to its own core, out of those this process may run on.
+    ///
+    /// Use with [`SchedulerBuilder::threads`](crate::SchedulerBuilder::threads)
+    /// set to at most the number of cores, or workers will share.
+    OnePerCore,
+}
+
+/// The cores the current thread may run on.
+pub(crate) fn allowed() -> io::Result<Vec<usize>> {
+    // SAFETY: `cpu_set_t` is a plain bitset, so zero is a valid (empty) set.
+    let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+    // SAFETY: `set` is valid for writes of its size.
+    if unsafe { libc::sched_getaffinity(0, mem::size_of_val(&set), &mut set) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let cores = (0..libc::CPU_SETSIZE as usize)
+        .filter(|&core| unsafe { libc::CPU_ISSET(core, &set) })
+        .collect();
+    Ok(cores)
+}
+
+/// Only let the current thread run on `cores`.
+pub(crate) fn set(cores: &[usize]) -> io::Result<()> {
+    // SAFETY: As above.
+    let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+    for &core in cores {
+        unsafe { libc::CPU_SET(core, &mut set) };
+    }
+    // SAFETY: `set` is valid for reads of its size.
+    if unsafe { libc::sched_setaffinity(0, mem::size_of_val(&set), &set) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use crate::{scheduler::SchedulerBuilder, when, CownPtr};
+
+    use super::*;
+
+    #[test]
+    fn pinned_to_core() {
+        static SEEN: Mutex<Vec<Vec<usize>>> = Mutex::new(Vec::new());
+
+        let before = allowed().unwrap();
+        let core = *before.last().unwrap();
+        SchedulerBuilder::new()
+            .threads(2)
+            .affinity(Affinity::Cores(vec![core]))
+            .run(|| {
+                for _ in 0..10 {
+                    let v = CownPtr::new(());
+                    when(&v, |_| SEEN.lock().unwrap().push(allowed().unwrap()));
+                }
+            });
+
+        let seen = SEEN.lock().unwrap();
+        assert_eq!(seen.len(), 10);
+        assert!(seen.iter().all(|cores| *cores == [core]), "{seen:?}");
+        // In case this thread was a worker.
+        assert_eq!(allowed().unwrap(), before);
+    }
+
+    #[test]
+    fn one_per_core() {
+        static SEEN: Mutex<Vec<(usize, Vec<usize>)>> = Mutex::new(Vec::new());
+
+        let available = allowed().unwrap();
+        SchedulerBuilder::new()
+            .threads(available.len())
+            .affinity(Affinity::OnePerCore)
+            .run(|| {
+                for _ in 0..100 {
+                    let v = CownPtr::new(());
+                    when(&v, |_| {
+                        let worker = crate::current_worker().unwrap().index();
+                        SEEN.lock().unwrap().push((worker, allowed().unwrap()));
+                    });
+                }
+            });
+
+        let seen = SEEN.lock().unwrap();
+        assert_eq!(seen.len(), 100);
+        // Exactly one core each, and as there are as many workers as cores, a
+        // different one for each worker.
+        for (worker, cores) in seen.iter() {
+            assert_eq!(*cores, [available[*worker]], "{seen:?}");
+        }
+    }
+}
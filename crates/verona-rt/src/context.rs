@@ -0,0 +1,104 @@
+//! Finding out where code is running: in a behaviour, on one of the
+//! scheduler's worker threads, or elsewhere.
+//!
+//! Useful for code shared between behaviours and other threads, e.g. to
+//! refuse to block inside a behaviour, or to decide whether to use [`when`]
+//! or access data directly.
+//!
+//! [`when`]: crate::when
+
+use std::{cell::Cell, marker::PhantomData, ptr::NonNull, vec::Vec};
+
+use verona_rt_sys as ffi;
+
+use crate::cown::CownId;
+
+thread_local! {
+    /// The cowns acquired by the behaviour running on this thread.
+    static BEHAVIOUR: Cell<Option<NonNull<[ffi::AcquiredCown]>>> = const { Cell::new(None) };
+}
+
+/// Marks the current thread as running a behaviour, until dropped.
+pub(crate) struct Behaviour<'a> {
+    previous: Option<NonNull<[ffi::AcquiredCown]>>,
+    _cowns: PhantomData<&'a [ffi::AcquiredCown]>,
+}
+
+impl<'a> Behaviour<'a> {
+    pub(crate) fn enter(cowns: &'a [ffi::AcquiredCown]) -> Self {
+        let previous = BEHAVIOUR.with(|b| b.replace(Some(NonNull::from(cowns))));
+        Self {
+            previous,
+            _cowns: PhantomData,
+        }
+    }
+}
+
+impl Drop for Behaviour<'_> {
+    fn drop(&mut self) {
+        BEHAVIOUR.with(|b| b.set(self.previous));
+    }
+}
+
+/// Whether this is running inside a behaviour.
+///
+/// ```rust
+/// # use verona_rt::*;
+/// # with_scheduler(|| {
+/// assert!(!in_behaviour());
+/// let v = CownPtr::new(());
+/// when(&v, |_| assert!(in_behaviour()));
+/// # });
+/// ```
+pub fn in_behaviour() -> bool {
+    BEHAVIOUR.with(|b| b.get().is_some())
+}
+
+/// Whether this is running on one of the scheduler's worker threads, whether
+/// or not it's in a behaviour.
+pub fn in_runtime() -> bool {
+    crate::current_worker().is_some()
+}
+
+/// The cowns acquired by the behaviour that's running, or `None` outside a
+/// behaviour.
+pub fn current_behaviour_cowns() -> Option<Vec<CownId>> {
+    let cowns = BEHAVIOUR.with(|b| b.get())?;
+    // SAFETY: `Behaviour` is only alive while the cowns it points to are.
+    let cowns = unsafe { cowns.as_ref() };
+    Some(cowns.iter().map(|c| CownId::from_addr(c.addr())).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use crate::{scheduler::SchedulerBuilder, when2, CownPtr};
+
+    use super::*;
+
+    #[test]
+    fn inside_behaviour() {
+        /// `in_behaviour`, `in_runtime` and `current_behaviour_cowns`.
+        type Seen = (bool, bool, Option<Vec<CownId>>);
+        static SEEN: Mutex<Option<Seen>> = Mutex::new(None);
+
+        assert!(!in_behaviour());
+        assert!(!in_runtime());
+        assert_eq!(current_behaviour_cowns(), None);
+
+        let ids = SchedulerBuilder::new().run(|| {
+            let a = CownPtr::new(1);
+            let b = CownPtr::new("b");
+            when2(&a, &b, |_, _| {
+                *SEEN.lock().unwrap() =
+                    Some((in_behaviour(), in_runtime(), current_behaviour_cowns()));
+            });
+            vec![a.id(), b.id()]
+        });
+
+        let seen = SEEN.lock().unwrap().take().unwrap();
+        assert_eq!(seen, (true, true, Some(ids)));
+        assert!(!in_behaviour());
+    }
+}
@@ -5,16 +5,22 @@ use core::{
     ptr,
 };
 
-use verona_rt_sys as ffi;
+use verona_rt_sys::{self as ffi, checked};
+
+use ffi::layout::{ALIGNOF_ACTUALCOWN, OBJECT_ALIGNMENT, SIZEOF_ACTUALCOWN, SIZEOF_OBJECT_HEADER};
 
 // See docs/layout.md for how this works.
 
 pub struct CownPtr<T> {
     pub(crate) cown_ptr: ffi::CownPtr,
-    // TODO: Is this right wrt send/sync.
     _marker: PhantomData<T>,
 }
 
+// The reference count is atomic, and the value is only accessed by
+// behaviours, which may run on any worker.
+unsafe impl<T: Send> Send for CownPtr<T> {}
+unsafe impl<T: Send> Sync for CownPtr<T> {}
+
 #[repr(C)]
 /// It's never safe to dereference this type, or even to construct one.
 pub(crate) struct CownDataToxic<T> {
@@ -46,7 +52,39 @@ impl<T> CownPtr<T> {
 #[repr(C)]
 #[derive(Debug)]
 struct ActualCown {
-    _marker: MaybeUninit<[*const (); 4]>,
+    _marker: MaybeUninit<[*const (); SIZEOF_ACTUALCOWN / mem::size_of::<*const ()>()]>,
+}
+
+// The C++ side asserts `ActualCown` is pointer aligned, so an array of
+// pointers gets the alignment right as long as the size is a multiple.
+const _: () = {
+    assert!(mem::size_of::<ActualCown>() == SIZEOF_ACTUALCOWN);
+    assert!(mem::align_of::<ActualCown>() == ALIGNOF_ACTUALCOWN);
+};
+
+/// Identifies a cown, whatever its type.
+///
+/// Only unique while the cown is alive: once it's freed, another cown may get
+/// the same id.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CownId(usize);
+
+impl CownId {
+    pub(crate) fn from_addr(addr: *mut ()) -> Self {
+        Self(addr as usize)
+    }
+}
+
+impl fmt::Debug for CownId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CownId({:#x})", self.0)
+    }
+}
+
+impl<T> CownPtr<T> {
+    pub fn id(&self) -> CownId {
+        CownId::from_addr(self.cown_ptr.addr())
+    }
 }
 
 impl<T> fmt::Pointer for CownPtr<T> {
@@ -57,21 +95,36 @@ impl<T> fmt::Pointer for CownPtr<T> {
 
 impl<T> core::ops::Drop for CownPtr<T> {
     fn drop(&mut self) {
-        unsafe { ffi::boxcar_cownptr_drop(&mut self.cown_ptr) };
+        #[cfg(feature = "native_refcount")]
+        if unsafe { crate::refcount::try_release(self.cown_ptr.addr()) } {
+            return;
+        }
+
+        unsafe { checked::cownptr_drop(&mut self.cown_ptr) };
     }
 }
 
 impl<T> Clone for crate::cown::CownPtr<T> {
+    #[cfg(feature = "native_refcount")]
     fn clone(&self) -> Self {
         unsafe {
-            let mut new = mem::zeroed();
-            ffi::boxcar_cownptr_clone(&self.cown_ptr, &mut new);
+            crate::refcount::acquire(self.cown_ptr.addr());
             Self {
-                cown_ptr: new,
+                // The new reference is the same pointer, now that we've taken
+                // a reference for it.
+                cown_ptr: ptr::read(&self.cown_ptr),
                 _marker: PhantomData,
             }
         }
     }
+
+    #[cfg(not(feature = "native_refcount"))]
+    fn clone(&self) -> Self {
+        Self {
+            cown_ptr: unsafe { checked::cownptr_clone(&self.cown_ptr) },
+            _marker: PhantomData,
+        }
+    }
 }
 
 extern "C" fn drop_glue<T>(cown: *mut ()) {
@@ -79,12 +132,15 @@ extern "C" fn drop_glue<T>(cown: *mut ()) {
     unsafe {
         ptr::drop_in_place(data_ptr);
     }
+    crate::leak::unregister(cown);
+    #[cfg(feature = "stats")]
+    crate::stats::cown_freed();
+    #[cfg(feature = "dep_graph")]
+    crate::dep_graph::cown_freed(cown as usize);
 }
 
-const SIZEOF_OBJECT_HEADER: usize = 16;
-const OBJECT_ALIGNMENT: usize = 16;
 const fn vsizeof<T>() -> usize {
-    use std::mem::size_of;
+    use core::mem::size_of;
     // The runtime stores an object header below the returned pointer, but we still need space for it in the allocation.
     align_up(size_of::<T>() + SIZEOF_OBJECT_HEADER, OBJECT_ALIGNMENT)
 }
@@ -98,20 +154,23 @@ impl<T> CownPtr<T> {
     const ALLOCATION_SIZE: usize = vsizeof::<CownDataToxic<T>>();
 
     /// Must be inside a runtime.
-    // TODO: Enforce that.
+    ///
+    /// ## Panics
+    ///
+    /// In debug builds, if called outside a scheduler session.
     pub fn new(value: T) -> Self {
-        unsafe {
-            // The C++ code called here will read from the old value of cown to attempt to free it.
-            // Luckely for us, `nullptr` is a valid value for a cown_ptr, and we can create one easily.
-            let mut cown_ptr = mem::zeroed();
-
-            ffi::boxcar_cownptr_new(Self::ALLOCATION_SIZE, drop_glue::<T>, &mut cown_ptr);
+        let cown_ptr = checked::cownptr_new(Self::ALLOCATION_SIZE, drop_glue::<T>)
+            .unwrap_or_else(|e| panic!("can't create a cown: {e}"));
 
+        unsafe {
             let this = Self {
                 cown_ptr,
                 _marker: PhantomData,
             };
             ptr::write(this.data_ptr(), value);
+            crate::leak::register::<T>(this.cown_ptr.addr(), Self::ALLOCATION_SIZE);
+            #[cfg(feature = "stats")]
+            crate::stats::cown_created();
 
             this
         }
@@ -164,9 +223,7 @@ mod tests {
 
     #[test]
     fn leak_detector_new() {
-        unsafe {
-            ffi::enable_logging();
-        }
+        checked::enable_logging();
 
         with_leak_detector(|| {
             let x = CownPtr::new(1010);
@@ -176,27 +233,17 @@ mod tests {
         });
     }
 
+    // The constants are generated at build time, but this still checks that
+    // they match the library we actually linked against.
     #[test]
     fn actualcown_constats_right() {
-        let mut sizeof_actualcown = 0;
-        let mut alignof_actualcown = 0;
-        let mut sizeof_object_header = 0;
-        let mut object_alignment = 0;
-
-        unsafe {
-            ffi::boxcar_size_info(
-                &mut sizeof_actualcown,
-                &mut alignof_actualcown,
-                &mut sizeof_object_header,
-                &mut object_alignment,
-            );
-        }
+        let info = checked::size_info();
 
-        assert_eq!(std::mem::size_of::<ActualCown>(), sizeof_actualcown);
-        assert_eq!(std::mem::align_of::<ActualCown>(), alignof_actualcown);
+        assert_eq!(std::mem::size_of::<ActualCown>(), info.sizeof_actualcown);
+        assert_eq!(std::mem::align_of::<ActualCown>(), info.alignof_actualcown);
 
-        assert_eq!(sizeof_object_header, SIZEOF_OBJECT_HEADER);
-        assert_eq!(object_alignment, OBJECT_ALIGNMENT)
+        assert_eq!(info.sizeof_object_header, SIZEOF_OBJECT_HEADER);
+        assert_eq!(info.object_alignment, OBJECT_ALIGNMENT)
     }
 
     #[test]
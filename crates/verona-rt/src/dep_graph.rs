@@ -0,0 +1,346 @@
+//! Recording the order behaviours are scheduled in, to see why they ran when
+//! they did.
+//!
+//! A behaviour runs once every behaviour scheduled before it on any of its
+//! cowns has finished. With the `dep_graph` feature, each behaviour is recorded
+//! with its cowns, and the behaviours it waits on: for each cown, the
+//! behaviour most recently scheduled on it. The result is a DAG, which can be
+//! written as Graphviz DOT or JSON:
+//!
+//! - On demand, with [`write_dot`], [`write_json`] or [`save`].
+//! - When each scheduler session finishes, if the `VERONA_DEP_GRAPH`
+//!   environment variable is set to a path.
+//!
+//! Behaviours are kept until [`clear`] is called, so the graph covers every
+//! session so far.
+//!
+//! See also docs/dep_graph.md.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, MutexGuard,
+    },
+};
+
+use crate::{json::escape, when::Origin};
+
+/// Environment variable naming where to save the graph when a session
+/// finishes. Written as JSON if it ends in `.json`, and DOT otherwise.
+pub const DEP_GRAPH_ENV_VAR: &str = "VERONA_DEP_GRAPH";
+
+static GRAPH: Mutex<Graph> = Mutex::new(Graph {
+    nodes: BTreeMap::new(),
+    last: None,
+    ran: 0,
+});
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+struct Graph {
+    nodes: BTreeMap<u64, Node>,
+    /// The last behaviour scheduled on each live cown, keyed by address.
+    ///
+    /// An `Option` as `HashMap::new` isn't const.
+    last: Option<HashMap<usize, u64>>,
+    /// How many behaviours have started running.
+    ran: u64,
+}
+
+struct Node {
+    origin: Origin,
+    cowns: Vec<usize>,
+    /// The behaviours this waits on, and the cown it waits on them for.
+    waits_on: Vec<(u64, usize)>,
+    /// The order this started running in, if it has.
+    ran: Option<u64>,
+}
+
+fn graph() -> MutexGuard<'static, Graph> {
+    GRAPH.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// An id for a new behaviour.
+pub(crate) fn next_id() -> u64 {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Record behaviours, as `(id, origin, cowns)`, in the order they're about to
+/// be handed to the runtime.
+///
+/// The graph is only locked while they're recorded, not while the runtime
+/// schedules them, so a behaviour scheduled on the same cown by another thread
+/// at the same moment may be recorded in the other order. Everything passed to
+/// one call is recorded together, so a batch is always in order.
+pub(crate) fn reserve<'a>(behaviours: impl IntoIterator<Item = (u64, &'a Origin, Vec<usize>)>) {
+    let mut graph = graph();
+    let graph = &mut *graph;
+    let last = graph.last.get_or_insert_with(HashMap::new);
+    for (id, origin, cowns) in behaviours {
+        let waits_on = cowns
+            .iter()
+            .filter_map(|&cown| last.insert(cown, id).map(|pred| (pred, cown)))
+            .collect();
+
+        graph.nodes.insert(
+            id,
+            Node {
+                origin: *origin,
+                cowns,
+                waits_on,
+                ran: None,
+            },
+        );
+    }
+}
+
+/// Forget behaviours from [`reserve`] that the runtime refused.
+///
+/// Anything recorded as waiting on them waits on what they waited on instead.
+pub(crate) fn unreserve(ids: &[u64]) {
+    let mut graph = graph();
+    let graph = &mut *graph;
+    // Latest first, so behaviours in a batch that wait on each other unwind.
+    for &id in ids.iter().rev() {
+        let Some(node) = graph.nodes.remove(&id) else {
+            continue;
+        };
+        let pred = |cown| {
+            node.waits_on
+                .iter()
+                .find(|&&(_, c)| c == cown)
+                .map(|&(pred, _)| pred)
+        };
+
+        for other in graph.nodes.values_mut() {
+            other.waits_on.retain_mut(|(waits_on, cown)| {
+                if *waits_on != id {
+                    return true;
+                }
+                match pred(*cown) {
+                    Some(pred) => {
+                        *waits_on = pred;
+                        true
+                    }
+                    None => false,
+                }
+            });
+        }
+        if let Some(last) = &mut graph.last {
+            for &cown in &node.cowns {
+                if last.get(&cown) == Some(&id) {
+                    match pred(cown) {
+                        Some(pred) => last.insert(cown, pred),
+                        None => last.remove(&cown),
+                    };
+                }
+            }
+        }
+    }
+}
+
+/// Record that behaviour `id` has started running.
+pub(crate) fn started(id: u64) {
+    let mut graph = graph();
+    let ran = graph.ran;
+    graph.ran += 1;
+    if let Some(node) = graph.nodes.get_mut(&id) {
+        node.ran = Some(ran);
+    }
+}
+
+/// Forget about a cown once it's freed, as its address may be reused.
+pub(crate) fn cown_freed(addr: usize) {
+    if let Some(last) = &mut graph().last {
+        last.remove(&addr);
+    }
+}
+
+/// Write the graph as Graphviz DOT.
+///
+/// Each behaviour is a node, labelled with its id, name, where it was
+/// scheduled, its cowns, and the order it started running in. Edges point from a behaviour to the behaviours that
+/// wait for it, and are labelled with the cown they wait for.
+pub fn write_dot(mut w: impl Write) -> io::Result<()> {
+    let graph = graph();
+
+    writeln!(w, "digraph behaviours {{")?;
+    writeln!(w, "  node [shape=box];")?;
+    for (id, node) in &graph.nodes {
+        let cowns = node
+            .cowns
+            .iter()
+            .map(|c| format!("{c:#x}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let ran = match node.ran {
+            Some(ran) => format!("ran #{ran}"),
+            None => "not run".to_owned(),
+        };
+        writeln!(
+            w,
+            r##"  b{id} [label="#{id} {}\n{}\n{cowns}\n{ran}"];"##,
+            escape(node.origin.name()),
+            escape(&node.origin.location.to_string()),
+        )?;
+        for (pred, cown) in &node.waits_on {
+            writeln!(w, r#"  b{pred} -> b{id} [label="{cown:#x}"];"#)?;
+        }
+    }
+    writeln!(w, "}}")
+}
+
+/// Write the graph as JSON.
+///
+/// ```json
+/// {"behaviours": [
+///   {"id": 1, "name": "...", "location": "src/main.rs:10:5",
+///    "cowns": ["0x..."], "ran": 1,
+///    "waits_on": [{"behaviour": 0, "cown": "0x..."}]}
+/// ]}
+/// ```
+pub fn write_json(mut w: impl Write) -> io::Result<()> {
+    let graph = graph();
+
+    write!(w, r#"{{"behaviours":["#)?;
+    for (i, (id, node)) in graph.nodes.iter().enumerate() {
+        let sep = if i == 0 { "" } else { "," };
+        let cowns = node
+            .cowns
+            .iter()
+            .map(|c| format!(r#""{c:#x}""#))
+            .collect::<Vec<_>>()
+            .join(",");
+        let waits_on = node
+            .waits_on
+            .iter()
+            .map(|(pred, cown)| format!(r#"{{"behaviour":{pred},"cown":"{cown:#x}"}}"#))
+            .collect::<Vec<_>>()
+            .join(",");
+        let ran = match node.ran {
+            Some(ran) => ran.to_string(),
+            None => "null".to_owned(),
+        };
+        write!(
+            w,
+            r#"{sep}
+{{"id":{id},"name":"{}","location":"{}","cowns":[{cowns}],"ran":{ran},"waits_on":[{waits_on}]}}"#,
+            escape(node.origin.name()),
+            escape(&node.origin.location.to_string()),
+        )?;
+    }
+    writeln!(w, "\n]}}")
+}
+
+/// Write the graph to `path`, as JSON if it ends in `.json`, and DOT
+/// otherwise.
+pub fn save(path: impl AsRef<Path>) -> io::Result<()> {
+    let path = path.as_ref();
+    let mut file = BufWriter::new(File::create(path)?);
+    if path.extension().is_some_and(|e| e == "json") {
+        write_json(&mut file)?;
+    } else {
+        write_dot(&mut file)?;
+    }
+    file.flush()
+}
+
+/// Forget every behaviour recorded so far.
+pub fn clear() {
+    let mut graph = graph();
+    graph.nodes.clear();
+    // Or behaviours scheduled later would wait on forgotten ones.
+    graph.last = None;
+    graph.ran = 0;
+}
+
+/// Called when a session finishes, to save the graph if asked to.
+pub(crate) fn session_finished() {
+    if let Some(path) = std::env::var_os(DEP_GRAPH_ENV_VAR) {
+        if let Err(e) = save(&path) {
+            eprintln!(
+                "can't save dependency graph to {}: {e}",
+                Path::new(&path).display()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{scheduler::SchedulerBuilder, when, when2, CownPtr};
+
+    use super::*;
+
+    #[test]
+    fn waits_on_previous_behaviour() {
+        let (a, b) = SchedulerBuilder::new().run(|| {
+            let a = CownPtr::new(0);
+            let b = CownPtr::new(0);
+            when(&a, |mut a| *a += 1);
+            when(&b, |mut b| *b += 1);
+            when2(&a, &b, |a, b| assert_eq!((*a, *b), (1, 1)));
+            (a.cown_ptr.addr() as usize, b.cown_ptr.addr() as usize)
+        });
+
+        // Other tests may be recording behaviours too, so find ours.
+        let graph = graph();
+        let (&last, node) = graph
+            .nodes
+            .iter()
+            .rev()
+            .find(|(_, node)| node.cowns == [a, b])
+            .unwrap();
+
+        assert_eq!(node.waits_on.len(), 2);
+        for &(pred, cown) in &node.waits_on {
+            assert!(pred < last);
+            assert_eq!(graph.nodes[&pred].cowns, [cown]);
+            assert!(graph.nodes[&pred].ran < node.ran);
+        }
+        assert!(node.ran.is_some());
+    }
+
+    #[test]
+    fn unreserve_skips_refused_behaviours() {
+        // Not real cowns, so other tests can't schedule on them.
+        let (a, b) = (usize::MAX - 1, usize::MAX - 3);
+        let origin = Origin::new::<()>(None);
+        let ids = [next_id(), next_id(), next_id()];
+        reserve([(ids[0], &origin, vec![a])]);
+        reserve([(ids[1], &origin, vec![a, b]), (ids[2], &origin, vec![b])]);
+
+        unreserve(&ids[1..2]);
+        {
+            let graph = graph();
+            assert!(!graph.nodes.contains_key(&ids[1]));
+            assert_eq!(graph.nodes[&ids[2]].waits_on, []);
+            let last = graph.last.as_ref().unwrap();
+            assert_eq!(last.get(&a), Some(&ids[0]));
+            assert_eq!(last.get(&b), Some(&ids[2]));
+        }
+
+        unreserve(&ids[2..]);
+        let graph = graph();
+        assert_eq!(graph.last.as_ref().unwrap().get(&b), None);
+    }
+
+    #[test]
+    fn dot() {
+        SchedulerBuilder::new().run(|| {
+            let a = CownPtr::new(0);
+            when(&a, |_| {});
+            when(&a, |_| {});
+        });
+
+        let mut dot = Vec::new();
+        write_dot(&mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.starts_with("digraph behaviours {"), "{dot}");
+        assert!(dot.contains(" -> "), "{dot}");
+        assert!(dot.contains("src/dep_graph.rs:"), "{dot}");
+    }
+}
@@ -0,0 +1,189 @@
+//! Tracking of live cowns, so the leak detector can say *what* leaked.
+//!
+//! snmalloc's leak detector only tells us that something is still allocated.
+//! To make that actionable, while leak detection is on, every
+//! [`CownPtr::new`](crate::CownPtr::new) records the cown here, and the cown's
+//! destructor removes it again. When a leak is detected, whatever is left is
+//! what leaked.
+//!
+//! Otherwise creating and dropping cowns would all go through one lock, so
+//! nothing is recorded while detection is off.
+//!
+//! See also docs/leak_detector.md for the limitations of the underlying
+//! detector.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::{
+    error::Error,
+    fmt,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use verona_rt_sys::checked::{self, SchedulerError};
+
+use crate::sync::{Mutex, MutexGuard};
+
+#[cfg(feature = "leak_backtrace")]
+use std::{backtrace::Backtrace, sync::Arc};
+
+/// All cowns that have been created, but not yet destroyed.
+///
+/// Keyed by the address of the cown.
+static LIVE_COWNS: Mutex<BTreeMap<usize, LeakedCown>> = Mutex::new(BTreeMap::new());
+/// Whether new cowns are recorded in `LIVE_COWNS`.
+static TRACKING: AtomicBool = AtomicBool::new(false);
+/// The number of cowns in `LIVE_COWNS`, so dropping a cown only takes the lock
+/// if it might be there.
+static TRACKED: AtomicUsize = AtomicUsize::new(0);
+
+/// Turn the runtime's leak detector on or off, and with it recording new
+/// cowns.
+///
+/// Cowns recorded while it was on are still removed when they're freed.
+pub(crate) fn set_detect_leaks(detect_leaks: bool) -> Result<(), SchedulerError> {
+    checked::set_detect_leaks(detect_leaks)?;
+    TRACKING.store(detect_leaks, Ordering::Relaxed);
+    Ok(())
+}
+
+pub(crate) fn register<T>(addr: *mut (), size: usize) {
+    if !TRACKING.load(Ordering::Relaxed) {
+        return;
+    }
+    let cown = LeakedCown {
+        addr: addr as usize,
+        type_name: core::any::type_name::<T>(),
+        size,
+        #[cfg(feature = "leak_backtrace")]
+        backtrace: Arc::new(Backtrace::force_capture()),
+    };
+
+    let mut live = live_cowns();
+    let old = live.insert(addr as usize, cown);
+    debug_assert!(old.is_none(), "cown {addr:p} registered twice");
+    TRACKED.store(live.len(), Ordering::Relaxed);
+}
+
+pub(crate) fn unregister(addr: *mut ()) {
+    // The cown was created before this, so if it was recorded, this sees it.
+    if TRACKED.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    let mut live = live_cowns();
+    // May not be there, if it was created while detection was off.
+    live.remove(&(addr as usize));
+    TRACKED.store(live.len(), Ordering::Relaxed);
+}
+
+fn live_cowns() -> MutexGuard<'static, BTreeMap<usize, LeakedCown>> {
+    LIVE_COWNS.lock()
+}
+
+/// Check if the runtime has leaked any memory.
+///
+/// This should be called after the scheduler has finished running, as
+/// behaviours that haven't run yet will still hold onto their cowns.
+///
+/// Like the underlying detector, this is global: once one session has leaked,
+/// every later check will also report that leak.
+pub fn check_leaks() -> Result<(), LeakReport> {
+    if checked::has_leaks() {
+        Err(LeakReport {
+            cowns: live_cowns().values().cloned().collect(),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// Description of memory leaked by the runtime.
+///
+/// Returned by [`check_leaks`].
+#[derive(Debug, Clone)]
+pub struct LeakReport {
+    cowns: Vec<LeakedCown>,
+}
+
+impl LeakReport {
+    /// The cowns that were still alive when the leak was detected.
+    ///
+    /// Only cowns created while leak detection was on are included, so this
+    /// may be empty, or the leaked memory may not be a cown at all.
+    pub fn cowns(&self) -> &[LeakedCown] {
+        &self.cowns
+    }
+}
+
+impl fmt::Display for LeakReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.cowns.is_empty() {
+            return write!(
+                f,
+                "no live cowns (the leak isn't a cown, or is one created while \
+                 leak detection was off)"
+            );
+        }
+
+        write!(f, "{} live cown(s)", self.cowns.len())?;
+        for cown in &self.cowns {
+            write!(f, "\n  {cown}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for LeakReport {}
+
+/// A cown that was alive when a leak was detected.
+#[derive(Debug, Clone)]
+pub struct LeakedCown {
+    addr: usize,
+    type_name: &'static str,
+    size: usize,
+    #[cfg(feature = "leak_backtrace")]
+    backtrace: Arc<Backtrace>,
+}
+
+impl LeakedCown {
+    /// The address of the cown.
+    ///
+    /// The cown may have been freed since, so this must not be dereferenced.
+    pub fn addr(&self) -> *const () {
+        self.addr as *const ()
+    }
+
+    /// The type of the value stored in the cown, as given by
+    /// [`core::any::type_name`].
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    /// The size of the allocation backing the cown, in bytes.
+    ///
+    /// This includes the runtime's object header and cown metadata, so is
+    /// larger than the value itself.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Where the cown was created.
+    #[cfg(feature = "leak_backtrace")]
+    pub fn backtrace(&self) -> &Backtrace {
+        &self.backtrace
+    }
+}
+
+impl fmt::Display for LeakedCown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:p}: {} ({} bytes)",
+            self.addr(),
+            self.type_name,
+            self.size
+        )?;
+        #[cfg(feature = "leak_backtrace")]
+        write!(f, "\n  created at:\n{}", self.backtrace)?;
+        Ok(())
+    }
+}
@@ -39,23 +39,74 @@
 //! 1. *Don't leak threads*: When the main thread finishes, all other threads
 //!    shut down. If you've accessed verona-rt resources in other threads,
 //!    you'll have a bad time.
-//! 2. *Run everything inside a schedular*: Use [`scheduler::with`] to set up and
-//!    tear down the global schedular state.
-//! 3. *Don't panic*: If you panic with the schedular, arbitrarily bad things happen.
-// !    I'm working on solving this, but it's on the backburner for now.
+//! 2. *Run everything inside a schedular*: Use [`with_scheduler`] (or a
+//!    [`SchedulerBuilder`]) to set up and tear down the global schedular state.
+//! 3. *Panics abort outside `std`*: Panics inside behaviours are caught, and
+//!    re-raised once the scheduler has finished running (see
+//!    [`RuntimeHandle`] for who sees them in a shared session). Without the
+//!    `std` feature they can't be caught, so must abort.
 //! 4. *Don't make a load of schedulers*: Everything should run with the same schedular.
-//!    If you call [`scheduler::with``] on a load of thread, your going to have a bad day
-//!    (unless you like debugging non-reproducible segfaults :)).
+//!    [`with_scheduler`] and [`SchedulerBuilder::run`] wait for exclusive use
+//!    of it, while [`RuntimeHandle`]s let several threads share it.
+//!
+//! ## `no_std`
+//!
+//! With `default-features = false`, this crate only needs `core` and `alloc`.
+//! The `CownPtr`/`when` API is the same, but features that need `std`
+//! (`systematic_testing`, `leak_backtrace`, `trace`, `dep_graph` and `stats`)
+//! are unavailable, as are timers, worker hooks, and `in_behaviour` and
+//! friends.
+
+#![cfg_attr(not(feature = "std"), no_std)]
 
-// It'd be nice, see #17, but we need mutex's
-// #![no_std]
+extern crate alloc;
 
+#[cfg(all(feature = "std", target_os = "linux"))]
+mod affinity;
+#[cfg(feature = "std")]
+mod context;
 mod cown;
+#[cfg(feature = "dep_graph")]
+pub mod dep_graph;
+#[cfg(any(feature = "trace", feature = "dep_graph"))]
+mod json;
+mod leak;
 mod log;
+#[cfg(feature = "native_refcount")]
+mod refcount;
+mod runtime;
 mod scheduler;
+mod spawner;
+#[cfg(feature = "stats")]
+mod stats;
+mod sync;
+#[cfg(feature = "systematic_testing")]
+pub mod systematic;
+#[cfg(feature = "std")]
+mod timer;
+#[cfg(feature = "trace")]
+pub mod trace;
 mod when;
+mod worker;
 
-pub use cown::CownPtr;
+#[cfg(all(feature = "std", target_os = "linux"))]
+pub use affinity::Affinity;
+#[cfg(feature = "std")]
+pub use context::{current_behaviour_cowns, in_behaviour, in_runtime};
+pub use cown::{CownId, CownPtr};
+pub use leak::{check_leaks, LeakReport, LeakedCown};
 pub use log::log;
-pub use scheduler::{with as with_scheduler, with_leak_detector};
-pub use when::{when, when2, AcquiredCown};
+pub use runtime::{ExternalSourceGuard, Runtime};
+pub use scheduler::{with as with_scheduler, with_leak_detector, RuntimeHandle, SchedulerBuilder};
+pub use spawner::Spawner;
+#[cfg(feature = "stats")]
+pub use stats::{RuntimeStats, StatsSampler, WorkerStats};
+#[cfg(feature = "std")]
+pub use timer::{every, when_after, TimerHandle};
+pub use verona_rt_macros::test;
+pub use when::{
+    schedule_many, when, when2, when2_cancellable, when_cancellable, AcquiredCown, Batch,
+    BehaviourBuilder, CancelToken,
+};
+#[cfg(feature = "std")]
+pub use worker::{current_worker, WorkerId};
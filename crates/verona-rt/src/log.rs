@@ -1,11 +1,36 @@
 // TODO: Richer logging abstractions.
 
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use alloc::{ffi::CString, format};
+
+use crate::when::Origin;
+
+/// Whether we've turned on the runtime's logging, so we only format
+/// messages when they'll be written.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn enable() {
+    verona_rt_sys::checked::enable_logging();
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Log `what` is happening to the behaviour from `origin`.
+pub(crate) fn behaviour(what: &str, origin: &Origin) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    // Interior nul bytes could only come from a label.
+    let line = format!("{what} {origin} ({})", origin.closure).replace('\0', "");
+    let line = CString::new(line).expect("nul bytes removed");
+    verona_rt_sys::checked::log_cstr(&line);
+    verona_rt_sys::checked::log_endl();
+}
+
 pub fn log(val: &'static core::ffi::CStr) {
     // TODO: Does this race?
-    unsafe {
-        verona_rt_sys::boxcar_log_cstr(val.as_ptr());
-        verona_rt_sys::boxcar_log_endl();
-    }
+    verona_rt_sys::checked::log_cstr(val);
+    verona_rt_sys::checked::log_endl();
 }
 
 /*
@@ -0,0 +1,158 @@
+//! Reference counting for cowns, done in Rust where possible.
+//!
+//! The strong count lives in the object header, just before the cown (see
+//! docs/layout.md). Incrementing it, and decrementing it when we're not the
+//! last reference, are single atomic operations, so we do them here rather than
+//! calling into C++. Releasing the last reference needs the runtime to collect
+//! the cown, so that still goes through [`checked::cownptr_drop`].
+//!
+//! The orderings match `verona::rt::Object::incref`/`decref`, which use
+//! sequentially consistent operations.
+//!
+//! Under systematic testing, the C++ `incref`/`decref` are yield points, where
+//! the runtime may switch to another thread. The fast path here skips them, so
+//! systematic testing explores fewer interleavings around cowns being cloned
+//! and dropped. Build without `native_refcount` to test those.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use verona_rt_sys::layout::{OBJECT_ONE_RC, OBJECT_RC_OFFSET, SIZEOF_OBJECT_HEADER};
+
+#[cfg(doc)]
+use verona_rt_sys::checked;
+
+/// The strong count of the cown at `cown`.
+///
+/// ## Safety
+///
+/// - `cown` must be a live cown.
+unsafe fn strong_count<'a>(cown: *mut ()) -> &'a AtomicUsize {
+    let header = (cown as *mut u8).sub(SIZEOF_OBJECT_HEADER);
+    &*(header.add(OBJECT_RC_OFFSET) as *const AtomicUsize)
+}
+
+/// Add a strong reference to `cown`.
+///
+/// ## Safety
+///
+/// - `cown` must be a live cown, that the caller holds a reference to.
+pub(crate) unsafe fn acquire(cown: *mut ()) {
+    strong_count(cown).fetch_add(OBJECT_ONE_RC, Ordering::SeqCst);
+}
+
+/// Try to release a strong reference to `cown`, without calling into C++.
+///
+/// Returns `false` if this might be the last reference, in which case the
+/// count hasn't been changed, and the caller must release it through C++.
+///
+/// ## Safety
+///
+/// - `cown` must be a live cown, and the caller must own the reference being
+///   released.
+pub(crate) unsafe fn try_release(cown: *mut ()) -> bool {
+    let count = strong_count(cown);
+    let mut current = count.load(Ordering::SeqCst);
+
+    loop {
+        // Any bits below `OBJECT_ONE_RC` aren't part of the count.
+        if current < 2 * OBJECT_ONE_RC {
+            return false;
+        }
+
+        match count.compare_exchange_weak(
+            current,
+            current - OBJECT_ONE_RC,
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+        ) {
+            Ok(_) => return true,
+            Err(actual) => current = actual,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use verona_rt_sys::checked;
+
+    use super::*;
+    use crate::{scheduler, when, CownPtr};
+
+    /// The count as C++ sees it, in references.
+    fn cpp_count<T>(c: &CownPtr<T>) -> usize {
+        unsafe { checked::cownptr_debug_rc(&c.cown_ptr) / OBJECT_ONE_RC }
+    }
+
+    /// The count as we see it, in references.
+    fn rust_count<T>(c: &CownPtr<T>) -> usize {
+        unsafe { strong_count(c.cown_ptr.addr()).load(Ordering::SeqCst) / OBJECT_ONE_RC }
+    }
+
+    #[test]
+    fn matches_cpp() {
+        scheduler::with_leak_detector(|| {
+            let c = CownPtr::new(10);
+            assert_eq!(rust_count(&c), 1);
+            assert_eq!(cpp_count(&c), 1);
+
+            let clones: Vec<_> = (0..10).map(|_| c.clone()).collect();
+            assert_eq!(rust_count(&c), 11);
+            assert_eq!(cpp_count(&c), 11);
+
+            drop(clones);
+            assert_eq!(rust_count(&c), 1);
+            assert_eq!(cpp_count(&c), 1);
+        })
+    }
+
+    #[test]
+    fn mixed_with_behaviours() {
+        // Behaviours hold references taken by C++, and release them on worker
+        // threads, while we release ours here.
+        scheduler::SchedulerBuilder::new()
+            .threads(4)
+            .detect_leaks(true)
+            .run(|| {
+                let c = CownPtr::new(0);
+                for _ in 0..100 {
+                    when(&c, |mut c| *c += 1);
+                    drop(c.clone());
+                }
+                when(&c, |c| assert_eq!(*c, 100));
+                assert_eq!(rust_count(&c), cpp_count(&c));
+            })
+    }
+
+    #[test]
+    #[cfg(feature = "systematic_testing")]
+    fn systematic_mixed_with_behaviours() {
+        let builder = scheduler::SchedulerBuilder::new()
+            .threads(4)
+            .detect_leaks(true);
+
+        crate::systematic::explore_with(builder, 0..100, || {
+            let a = CownPtr::new(0);
+            let b = CownPtr::new(0);
+            for _ in 0..10 {
+                crate::when2(&a, &b, |mut a, mut b| {
+                    *a += 1;
+                    *b += 1;
+                });
+                let a2 = a.clone();
+                when(&a2, |mut a| *a += 1);
+            }
+            // Behaviours hold references taken by C++ until they've run.
+            assert_eq!(rust_count(&a), cpp_count(&a));
+            assert_eq!(rust_count(&b), cpp_count(&b));
+
+            let (a2, b2) = (a.clone(), b.clone());
+            crate::when2(&a, &b, move |a, b| {
+                assert_eq!(*a, 20);
+                assert_eq!(*b, 10);
+                // By now, the other behaviours have released theirs.
+                assert_eq!(rust_count(&a2), cpp_count(&a2));
+                assert_eq!(rust_count(&b2), cpp_count(&b2));
+            });
+        })
+    }
+}
@@ -0,0 +1,146 @@
+//! Operations on the global runtime, rather than a particular session.
+
+#[cfg(feature = "stats")]
+use std::time::Duration;
+
+use verona_rt_sys::checked;
+
+#[cfg(feature = "stats")]
+use crate::stats::{RuntimeStats, StatsSampler};
+
+/// The global verona runtime.
+///
+/// There's only one, so this is never constructed, and just groups functions
+/// that act on it.
+#[derive(Debug)]
+pub struct Runtime {
+    _private: (),
+}
+
+impl Runtime {
+    /// Keep the scheduler running until the returned guard is dropped, even if
+    /// it has no work.
+    ///
+    /// Normally the scheduler finishes as soon as it runs out of behaviours.
+    /// A service waiting for outside events (network, timers, another thread)
+    /// has nothing queued while it waits, so should hold a guard for as long as
+    /// events can arrive. The workers park while there's no work.
+    ///
+    /// ```rust
+    /// # use verona_rt::*;
+    /// use std::sync::mpsc;
+    ///
+    /// let (tx, rx) = mpsc::channel();
+    /// let producer = std::thread::spawn(move || tx.send(42).unwrap());
+    ///
+    /// let listener = with_scheduler(|| {
+    ///     let guard = Runtime::add_external_event_source();
+    ///     std::thread::spawn(move || {
+    ///         for event in rx {
+    ///             when(&CownPtr::new(event), |e| assert_eq!(*e, 42));
+    ///         }
+    ///         drop(guard);
+    ///     })
+    /// });
+    /// producer.join().unwrap();
+    /// listener.join().unwrap();
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Outside of a scheduler session.
+    pub fn add_external_event_source() -> ExternalSourceGuard {
+        checked::add_external_event_source()
+            .unwrap_or_else(|e| panic!("can't add an external event source: {e}"));
+        ExternalSourceGuard { _private: () }
+    }
+
+    /// A snapshot of the runtime's counters for the current session, or the
+    /// last one if none is active.
+    ///
+    /// Can be called from any thread, at any time.
+    #[cfg(feature = "stats")]
+    pub fn stats() -> RuntimeStats {
+        crate::stats::snapshot()
+    }
+
+    /// Call `f` with a snapshot of the stats every `interval`, on a background
+    /// thread, until the returned sampler is dropped.
+    ///
+    /// The sampler doesn't keep the scheduler running.
+    #[cfg(feature = "stats")]
+    pub fn sample_stats(
+        interval: Duration,
+        f: impl FnMut(RuntimeStats) + Send + 'static,
+    ) -> StatsSampler {
+        StatsSampler::start(interval, f)
+    }
+}
+
+/// Keeps the scheduler running while alive.
+///
+/// Returned by [`Runtime::add_external_event_source`].
+#[derive(Debug)]
+#[must_use = "the scheduler can finish as soon as this is dropped"]
+pub struct ExternalSourceGuard {
+    _private: (),
+}
+
+impl Drop for ExternalSourceGuard {
+    fn drop(&mut self) {
+        // SAFETY: Added in `Runtime::add_external_event_source`, and the
+        // session can't end while this is alive.
+        unsafe { checked::remove_external_event_source() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            mpsc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use crate::{scheduler::SchedulerBuilder, when, CownPtr};
+
+    use super::*;
+
+    #[test]
+    fn channel_events() {
+        static SUM: AtomicU32 = AtomicU32::new(0);
+
+        let (tx, rx) = mpsc::channel::<u32>();
+
+        let (producer, consumer) = SchedulerBuilder::new().threads(2).run(|| {
+            let guard = Runtime::add_external_event_source();
+
+            let consumer = thread::spawn(move || {
+                for event in rx {
+                    when(&CownPtr::new(event), |e| {
+                        SUM.fetch_add(*e, Ordering::SeqCst);
+                    });
+                }
+                drop(guard);
+            });
+
+            let producer = thread::spawn(move || {
+                for event in 1..=10 {
+                    // Slow enough that the workers run out of work, and park,
+                    // between events.
+                    thread::sleep(Duration::from_millis(1));
+                    tx.send(event).unwrap();
+                }
+            });
+
+            (producer, consumer)
+        });
+
+        assert_eq!(SUM.load(Ordering::SeqCst), 55);
+        producer.join().unwrap();
+        consumer.join().unwrap();
+    }
+}
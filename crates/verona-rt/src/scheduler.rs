@@ -1,6 +1,3 @@
-use std::sync::Mutex;
-
-use ffi::scheduler_get;
 /// Access to the verona schedular.
 ///
 /// ## Global singleton
@@ -8,62 +5,503 @@ use ffi::scheduler_get;
 /// In each program there is exactly one global schedular. This is a big pile
 /// of global state, that must be carefully managed.
 ///
-/// TODO: Understand init/run well.
-use verona_rt_sys as ffi;
+/// The scheduler is either idle, shared between any number of
+/// [`RuntimeHandle`]s, owned by one exclusive session (from
+/// [`SchedulerBuilder::run`]), or running. Running means `scheduler_run` is
+/// draining the remaining behaviours, after which it's idle again.
+use alloc::{format, string::String, sync::Arc};
+use core::fmt;
+
+use verona_rt_sys::checked::{self, SchedulerError};
+
+#[cfg(feature = "std")]
+use crate::worker::WorkerId;
+use crate::{
+    sync::{Condvar, Mutex, MutexGuard},
+    worker::{self, Hooks},
+};
+
+static STATE: Mutex<State> = Mutex::new(State {
+    session: Session::Idle,
+    generation: 0,
+    hooks: Hooks::NONE,
+    detect_leaks: false,
+    failure: None,
+});
+/// Notified whenever `STATE` goes back to idle.
+static IDLE: Condvar = Condvar::new();
 
-fn get() -> ffi::Scheduler {
-    // TODO: Is it worth caching the returned pointer?
+struct State {
+    session: Session,
+    /// Number of sessions that have finished running.
+    generation: u64,
+    /// For the workers of the shared session.
+    hooks: Hooks,
+    /// Whether any participant in the shared session asked for leak
+    /// detection.
+    detect_leaks: bool,
+    /// Where the shared session says why it failed, if it did. Each of its
+    /// handles has a copy, so a later session can't overwrite it.
+    failure: Option<Failure>,
+}
+
+type Failure = Arc<Mutex<Option<String>>>;
 
-    // SAFETY: scheduler_get is safe to call.
-    unsafe { ffi::scheduler_get() }
+enum Session {
+    Idle,
+    Shared { handles: usize },
+    Exclusive,
+    Running,
 }
 
-static SCHED_LOCK: Mutex<()> = Mutex::new(());
+/// Wait until the scheduler is idle, and return the locked state.
+fn lock_idle() -> MutexGuard<'static, State> {
+    let mut state = STATE.lock();
+    while !matches!(state.session, Session::Idle) {
+        state = IDLE.wait(state);
+    }
+    state
+}
+
+/// Mark the current session as finished, and wake anyone waiting for it.
+fn finish_session() {
+    #[cfg(feature = "trace")]
+    crate::trace::session_finished();
+    #[cfg(feature = "dep_graph")]
+    crate::dep_graph::session_finished();
+
+    let mut state = STATE.lock();
+    state.session = Session::Idle;
+    state.generation += 1;
+    drop(state);
+    IDLE.notify_all();
+}
+
+/// Panic if the scheduler wasn't in the state we expected.
+///
+/// `STATE` should prevent this, so this is a bug in this crate.
+#[track_caller]
+fn expect_state(result: Result<(), SchedulerError>) {
+    if let Err(e) = result {
+        panic!("scheduler in unexpected state: {e}");
+    }
+}
 
-struct DropGuard;
+struct DropGuard(Hooks);
 impl Drop for DropGuard {
     fn drop(&mut self) {
-        unsafe { ffi::scheduler_run(get()) }
+        expect_state(worker::run(&self.0))
     }
 }
 
+/// Run `f` on the scheduler, and wait until every behaviour it scheduled has
+/// finished.
+///
+/// This has the runtime to itself: calls from other threads wait their turn,
+/// as do [`RuntimeHandle`]s. Leak detection is on, so this panics with the
+/// [`LeakReport`](crate::LeakReport) if anything leaked.
+///
+/// Shorthand for `SchedulerBuilder::new().detect_leaks(true).run(f)`.
 pub fn with<T: Send>(f: impl FnOnce() -> T + Send) -> T {
-    with_inner(f, true)
+    SchedulerBuilder::new().detect_leaks(true).run(f)
+}
+
+pub fn with_leak_detector<T>(f: impl FnOnce() -> T) -> T {
+    SchedulerBuilder::new().detect_leaks(true).run(f)
+}
+
+/// A reference to the shared runtime.
+///
+/// The first handle initializes the scheduler, and behaviours can be
+/// scheduled while any handle is alive. When the last handle is dropped, the
+/// thread dropping it runs the scheduler until every behaviour has finished.
+/// Handles acquired after that wait for it to finish, and start a new session.
+///
+/// Unlike [`with_scheduler`](crate::with_scheduler), this lets several threads
+/// use the runtime at once. If a behaviour panics, every participant that
+/// [`wait`](Self::wait)s panics, as there's no telling whose behaviour it was.
+/// The thread that drops the last handle resumes the original panic, and the
+/// others panic with its message. Likewise, if any participant asked for leak
+/// detection, every waiter panics if anything leaked.
+///
+/// ```rust
+/// # use verona_rt::*;
+/// let runtime = RuntimeHandle::acquire();
+/// let worker = runtime.clone();
+/// std::thread::spawn(move || {
+///     let v = CownPtr::new(10);
+///     when(&v, |v| assert_eq!(*v, 10));
+///     drop(worker);
+/// })
+/// .join()
+/// .unwrap();
+/// runtime.wait();
+/// ```
+pub struct RuntimeHandle {
+    generation: u64,
+    failure: Failure,
+}
+
+impl fmt::Debug for RuntimeHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RuntimeHandle")
+            .field("generation", &self.generation)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RuntimeHandle {
+    /// Join the shared runtime, starting it with the default configuration if
+    /// it isn't already.
+    ///
+    /// Blocks while an exclusive session is active, or the runtime is
+    /// finishing.
+    pub fn acquire() -> Self {
+        SchedulerBuilder::new().acquire()
+    }
+
+    /// Drop this handle, then wait until the session it belongs to has
+    /// finished running.
+    ///
+    /// # Panics
+    ///
+    /// If a behaviour in the session panicked, or leak detection was on and
+    /// anything leaked. See [`RuntimeHandle`].
+    pub fn wait(self) {
+        let generation = self.generation;
+        let failure = self.failure.clone();
+        drop(self);
+
+        let mut state = STATE.lock();
+        while state.generation == generation {
+            state = IDLE.wait(state);
+        }
+        drop(state);
+
+        let failure = failure.lock().clone();
+        if let Some(failure) = failure {
+            panic!("{failure}");
+        }
+    }
 }
 
-pub fn with_inner<T, F: FnOnce() -> T>(f: F, detect_leaks: bool) -> T {
-    let lock = SCHED_LOCK.lock();
+impl Clone for RuntimeHandle {
+    fn clone(&self) -> Self {
+        let mut state = STATE.lock();
+        let Session::Shared { handles } = &mut state.session else {
+            unreachable!("runtime handle outside a shared session");
+        };
+        *handles += 1;
+        Self {
+            generation: self.generation,
+            failure: self.failure.clone(),
+        }
+    }
+}
 
-    unsafe {
-        ffi::scheduler_init(scheduler_get(), 1);
+impl Drop for RuntimeHandle {
+    fn drop(&mut self) {
+        let mut state = STATE.lock();
+        let Session::Shared { handles } = &mut state.session else {
+            unreachable!("runtime handle outside a shared session");
+        };
+        *handles -= 1;
+        if *handles != 0 {
+            return;
+        }
+        state.session = Session::Running;
+        let hooks = core::mem::replace(&mut state.hooks, Hooks::NONE);
+        let detect_leaks = core::mem::take(&mut state.detect_leaks);
+        state.failure = None;
+        drop(state);
 
+        expect_state(worker::run(&hooks));
+        #[cfg(feature = "std")]
+        let panic = crate::when::take_behaviour_panic();
+        // Without `std`, a panicking behaviour aborts.
+        #[cfg(feature = "std")]
+        let panicked = panic.is_some();
+        #[cfg(not(feature = "std"))]
+        let panicked = false;
+
+        // Check before anyone else can start a session. A panicking behaviour
+        // may well have leaked, so that's reported instead.
+        let leaks = if detect_leaks && !panicked {
+            crate::leak::check_leaks().err()
+        } else {
+            None
+        };
         if detect_leaks {
-            ffi::schedular_set_detect_leaks(true);
+            expect_state(crate::leak::set_detect_leaks(false));
+        }
+        #[cfg(feature = "std")]
+        let failure = panic.as_ref().map(|payload| {
+            let message = match payload.downcast_ref::<&str>() {
+                Some(s) => s,
+                None => payload
+                    .downcast_ref::<String>()
+                    .map_or("Box<dyn Any>", |s| s),
+            };
+            format!("behaviour panicked in shared session: {message}")
+        });
+        #[cfg(not(feature = "std"))]
+        let failure = None;
+        *self.failure.lock() =
+            failure.or_else(|| Some(format!("leaks detected: {}", leaks.as_ref()?)));
+        finish_session();
+
+        #[cfg(feature = "std")]
+        if std::thread::panicking() {
+            return;
+        }
+        #[cfg(feature = "std")]
+        if let Some(payload) = panic {
+            std::panic::resume_unwind(payload);
         }
+        if let Some(report) = leaks {
+            panic!("leaks detected: {report}");
+        }
+    }
+}
+
+/// Configuration for a scheduler session.
+///
+/// ```rust
+/// # use verona_rt::*;
+/// SchedulerBuilder::new().threads(4).run(|| {
+///     let v = CownPtr::new(10);
+///     when(&v, |v| assert_eq!(*v, 10));
+/// });
+/// ```
+#[derive(Debug, Clone)]
+pub struct SchedulerBuilder {
+    threads: usize,
+    detect_leaks: bool,
+    logging: bool,
+    hooks: Hooks,
+    #[cfg(feature = "systematic_testing")]
+    seed: Option<u64>,
+}
+
+impl Default for SchedulerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SchedulerBuilder {
+    pub fn new() -> Self {
+        Self {
+            threads: 1,
+            detect_leaks: false,
+            logging: false,
+            hooks: Hooks::NONE,
+            #[cfg(feature = "systematic_testing")]
+            seed: None,
+        }
+    }
+
+    /// Number of worker threads to run behaviours on. Defaults to 1.
+    pub fn threads(mut self, threads: usize) -> Self {
+        assert_ne!(threads, 0, "scheduler needs at least one thread");
+        self.threads = threads;
+        self
     }
 
-    // Use a drop guard to clean up scheduler resources even in the case that
-    // The closure panics.
-    let dg = DropGuard;
-    let result = f();
-    drop(dg); // Calls Scheduler.run
+    /// Panic with a [`LeakReport`](crate::LeakReport) if anything leaked once
+    /// the session is over.
+    pub fn detect_leaks(mut self, detect_leaks: bool) -> Self {
+        self.detect_leaks = detect_leaks;
+        self
+    }
+
+    /// Enable the runtime's logging.
+    ///
+    /// Logging is global, so stays enabled once the session is over.
+    pub fn logging(mut self, logging: bool) -> Self {
+        self.logging = logging;
+        self
+    }
+
+    /// Call `f` on each worker thread as it starts, before it runs any
+    /// behaviours.
+    ///
+    /// Use this to name threads, set up thread-locals, or register workers
+    /// with a profiler. [`current_worker`](crate::current_worker) returns the
+    /// same id while the worker is running.
+    ///
+    /// Workers start concurrently, so `f` may be called from several threads
+    /// at once. If it panics, the process aborts.
+    #[cfg(feature = "std")]
+    pub fn on_thread_start(mut self, f: impl Fn(WorkerId) + Send + Sync + 'static) -> Self {
+        self.hooks.start = Some(Arc::new(f));
+        self
+    }
+
+    /// Call `f` on each worker thread once it has stopped running behaviours,
+    /// before the session finishes.
+    ///
+    /// If it panics, the process aborts.
+    #[cfg(feature = "std")]
+    pub fn on_thread_stop(mut self, f: impl Fn(WorkerId) + Send + Sync + 'static) -> Self {
+        self.hooks.stop = Some(Arc::new(f));
+        self
+    }
 
-    if detect_leaks {
-        unsafe {
-            if ffi::schedular_has_leaks() {
-                panic!("leaks detected");
+    /// Pin worker threads to cores. See [`Affinity`](crate::Affinity).
+    ///
+    /// The cores are worked out here, before the scheduler starts, rather
+    /// than by each worker.
+    ///
+    /// # Panics
+    ///
+    /// If a listed core isn't one this process may run on, or the cores it
+    /// may run on can't be found.
+    #[cfg(all(feature = "std", target_os = "linux"))]
+    pub fn affinity(mut self, affinity: crate::Affinity) -> Self {
+        let allowed =
+            crate::affinity::allowed().unwrap_or_else(|e| panic!("can't get available cores: {e}"));
+        let cores = match affinity {
+            crate::Affinity::Cores(cores) => {
+                assert!(!cores.is_empty(), "affinity needs at least one core");
+                for core in &cores {
+                    assert!(allowed.contains(core), "core {core} isn't available");
+                }
+                cores
             }
-            ffi::schedular_set_detect_leaks(false)
+            crate::Affinity::OnePerCore => allowed,
+        };
+        self.hooks.cores = Some(cores);
+        self
+    }
+
+    /// Seed for the interleaving chosen by systematic testing.
+    ///
+    /// The `VERONA_SEED` environment variable takes priority over this, so a
+    /// failing seed can be replayed without editing code.
+    #[cfg(feature = "systematic_testing")]
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Start the shared runtime with this configuration, or join it if it's
+    /// already started. See [`RuntimeHandle`].
+    ///
+    /// Only the number of threads, logging, thread hooks and affinity apply to
+    /// the shared runtime, and only if this call starts it. Leak detection
+    /// applies to the whole session if any participant asks for it, and the
+    /// handle that finishes the session panics if anything leaked.
+    ///
+    /// # Panics
+    ///
+    /// If a seed is set, as that needs an exclusive session.
+    pub fn acquire(self) -> RuntimeHandle {
+        #[cfg(feature = "systematic_testing")]
+        assert!(
+            self.seed.is_none(),
+            "seeds need an exclusive session, use SchedulerBuilder::run"
+        );
+
+        let mut state = STATE.lock();
+        loop {
+            match &mut state.session {
+                Session::Idle => {
+                    if self.logging {
+                        crate::log::enable();
+                    }
+                    expect_state(checked::init(self.threads));
+                    #[cfg(feature = "stats")]
+                    crate::stats::reset();
+                    state.session = Session::Shared { handles: 1 };
+                    state.hooks = self.hooks;
+                    state.failure = Some(Arc::new(Mutex::new(None)));
+                    break;
+                }
+                Session::Shared { handles } => {
+                    *handles += 1;
+                    break;
+                }
+                Session::Exclusive | Session::Running => state = IDLE.wait(state),
+            }
+        }
+
+        if self.detect_leaks && !state.detect_leaks {
+            expect_state(crate::leak::set_detect_leaks(true));
+            state.detect_leaks = true;
+        }
+
+        RuntimeHandle {
+            generation: state.generation,
+            failure: state.failure.clone().unwrap(),
         }
     }
 
-    drop(lock);
+    /// Run `f` inside an exclusive scheduler session, then run the scheduler
+    /// until all behaviours have finished.
+    ///
+    /// Waits for the shared runtime, or any other exclusive session, to
+    /// finish first, and nothing else can use the scheduler until this
+    /// returns.
+    ///
+    /// If any behaviour panicked, the first panic is resumed once the
+    /// scheduler has finished.
+    pub fn run<T>(self, f: impl FnOnce() -> T) -> T {
+        let mut state = lock_idle();
+        state.session = Session::Exclusive;
+        drop(state);
 
-    result
-}
+        // Give up the session however we leave, so a panic doesn't block
+        // everyone else.
+        struct SessionGuard;
+        impl Drop for SessionGuard {
+            fn drop(&mut self) {
+                finish_session();
+            }
+        }
+        let session = SessionGuard;
 
-pub fn with_leak_detector<T>(f: impl FnOnce() -> T) -> T {
-    with_inner(f, true)
+        #[cfg(feature = "systematic_testing")]
+        let _seed_reporter = crate::systematic::SeedReporter::install(self.seed);
+
+        if self.logging {
+            crate::log::enable();
+        }
+
+        expect_state(checked::init(self.threads));
+        #[cfg(feature = "stats")]
+        crate::stats::reset();
+
+        if self.detect_leaks {
+            expect_state(crate::leak::set_detect_leaks(true));
+        }
+
+        // Use a drop guard to clean up scheduler resources even in the case that
+        // The closure panics.
+        let dg = DropGuard(self.hooks.clone());
+        let result = f();
+        drop(dg); // Calls Scheduler.run
+
+        #[cfg(feature = "std")]
+        if let Some(payload) = crate::when::take_behaviour_panic() {
+            if self.detect_leaks {
+                expect_state(crate::leak::set_detect_leaks(false));
+            }
+            std::panic::resume_unwind(payload);
+        }
+
+        if self.detect_leaks {
+            let leaks = crate::leak::check_leaks();
+            expect_state(crate::leak::set_detect_leaks(false));
+            if let Err(report) = leaks {
+                panic!("leaks detected: {report}");
+            }
+        }
+
+        drop(session);
+
+        result
+    }
 }
 
 #[cfg(test)]
@@ -88,6 +526,71 @@ mod tests {
         });
     }
 
+    #[test]
+    fn shared_runtime() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        use crate::{when, CownPtr};
+
+        static RUN: AtomicUsize = AtomicUsize::new(0);
+
+        let runtime = RuntimeHandle::acquire();
+        std::thread::scope(|s| {
+            for _ in 0..10 {
+                let handle = runtime.clone();
+                s.spawn(move || {
+                    let v = CownPtr::new(());
+                    when(&v, |_| {
+                        RUN.fetch_add(1, Ordering::SeqCst);
+                    });
+                    drop(handle);
+                });
+            }
+        });
+        runtime.wait();
+
+        assert_eq!(RUN.load(Ordering::SeqCst), 10);
+    }
+
+    #[test]
+    fn exclusive_after_shared() {
+        let runtime = RuntimeHandle::acquire();
+        let t = std::thread::spawn(|| SchedulerBuilder::new().threads(2).run(|| 1));
+        runtime.wait();
+        assert_eq!(t.join().unwrap(), 1);
+    }
+
+    #[test]
+    fn thread_hooks() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        use crate::{current_worker, when, CownPtr};
+
+        static STARTED: AtomicUsize = AtomicUsize::new(0);
+        static STOPPED: AtomicUsize = AtomicUsize::new(0);
+
+        SchedulerBuilder::new()
+            .threads(2)
+            .on_thread_start(|id| {
+                assert_eq!(current_worker(), Some(id));
+                STARTED.fetch_add(1, Ordering::SeqCst);
+            })
+            .on_thread_stop(|_| {
+                STOPPED.fetch_add(1, Ordering::SeqCst);
+            })
+            .run(|| {
+                let v = CownPtr::new(());
+                when(&v, |_| {
+                    let id = current_worker().expect("behaviour outside a worker");
+                    assert!(id.index() < 2);
+                });
+            });
+
+        assert_eq!(current_worker(), None);
+        assert_eq!(STARTED.load(Ordering::SeqCst), 2);
+        assert_eq!(STOPPED.load(Ordering::SeqCst), 2);
+    }
+
     #[test]
     #[ignore = "https://github.com/aDotInTheVoid/boxcars/issues/4"]
     fn panic_safe() {
@@ -0,0 +1,116 @@
+//! Scheduling work from threads that aren't workers.
+//!
+//! Normally the scheduler finishes as soon as it runs out of work, so anything
+//! scheduled from another thread after that would be lost. A [`Spawner`]
+//! holds an [`ExternalSourceGuard`], which keeps it running until the spawner
+//! is dropped.
+
+use alloc::sync::Arc;
+
+use crate::{
+    cown::CownPtr,
+    runtime::{ExternalSourceGuard, Runtime},
+    when::AcquiredCown,
+};
+
+/// A handle for creating cowns and scheduling behaviours from any thread,
+/// including while the scheduler is running.
+///
+/// The scheduler doesn't finish while any clone of a `Spawner` is alive.
+///
+/// ```rust
+/// # use verona_rt::*;
+/// let thread = with_scheduler(|| {
+///     let spawner = Spawner::new();
+///     std::thread::spawn(move || {
+///         let v = spawner.cown(10);
+///         spawner.when(&v, |v| assert_eq!(*v, 10));
+///     })
+/// });
+/// thread.join().unwrap();
+/// ```
+#[derive(Clone)]
+pub struct Spawner {
+    _source: Arc<ExternalSourceGuard>,
+}
+
+impl Spawner {
+    /// Create a spawner for the current session.
+    ///
+    /// # Panics
+    ///
+    /// Outside of a scheduler session.
+    pub fn new() -> Self {
+        Self {
+            _source: Arc::new(Runtime::add_external_event_source()),
+        }
+    }
+
+    /// Create a new cown. See [`CownPtr::new`].
+    pub fn cown<T>(&self, value: T) -> CownPtr<T> {
+        CownPtr::new(value)
+    }
+
+    /// Schedule a behaviour on one cown. See [`when`](crate::when).
+    #[track_caller]
+    pub fn when<T, F>(&self, cown: &CownPtr<T>, f: F)
+    where
+        T: Send,
+        F: FnOnce(AcquiredCown<'_, T>) + Send + 'static,
+    {
+        crate::when(cown, f)
+    }
+
+    /// Schedule a behaviour on two cowns. See [`when2`](crate::when2).
+    #[track_caller]
+    pub fn when2<T, U, F>(&self, c1: &CownPtr<T>, c2: &CownPtr<U>, f: F)
+    where
+        T: Send,
+        U: Send,
+        F: FnOnce(AcquiredCown<'_, T>, AcquiredCown<'_, U>) + Send + 'static,
+    {
+        crate::when2(c1, c2, f)
+    }
+}
+
+impl Default for Spawner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    use crate::scheduler::SchedulerBuilder;
+
+    use super::*;
+
+    #[test]
+    fn schedule_while_running() {
+        static RUN: AtomicUsize = AtomicUsize::new(0);
+
+        let thread = SchedulerBuilder::new().threads(2).run(|| {
+            let spawner = Spawner::new();
+            thread::spawn(move || {
+                // Give the scheduler time to start running.
+                thread::sleep(Duration::from_millis(10));
+                for i in 0..10 {
+                    let c = spawner.cown(i);
+                    spawner.when(&c, |_| {
+                        RUN.fetch_add(1, Ordering::SeqCst);
+                    });
+                }
+            })
+        });
+
+        // The scheduler can't have finished until the spawner was dropped.
+        assert_eq!(RUN.load(Ordering::SeqCst), 10);
+        thread.join().unwrap();
+    }
+}
@@ -0,0 +1,267 @@
+//! Counters describing what the runtime is doing.
+//!
+//! Only built with the `stats` feature, as timing adds to every behaviour.
+//!
+//! Everything is measured in Rust: behaviours are counted as they're handed to
+//! the runtime, and timed by the trampolines, on the worker that runs them.
+//! Nothing comes from verona-rt's scheduler threads. It only counts steals and
+//! the like when built with `USE_SCHED_STATS`, and the bindings don't export
+//! them, so there's no queue depth, steal count or idle time here.
+//!
+//! See also docs/stats.md.
+//!
+//! The counters are reset when a session starts, so cover the current (or most
+//! recent) session.
+
+use std::{
+    cell::RefCell,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+static SCHEDULED: AtomicU64 = AtomicU64::new(0);
+static COMPLETED: AtomicU64 = AtomicU64::new(0);
+/// Total time from `when` to the behaviour starting, in nanoseconds, over
+/// completed behaviours.
+static LATENCY: AtomicU64 = AtomicU64::new(0);
+/// Not reset, as cowns outlive sessions.
+static LIVE_COWNS: AtomicUsize = AtomicUsize::new(0);
+/// Bumped by [`reset`], so workers from an old session re-register.
+static SESSION: AtomicU64 = AtomicU64::new(0);
+static WORKERS: Mutex<Vec<Arc<Worker>>> = Mutex::new(Vec::new());
+
+thread_local! {
+    static WORKER: RefCell<Option<Arc<Worker>>> = const { RefCell::new(None) };
+}
+
+struct Worker {
+    session: u64,
+    behaviours: AtomicU64,
+    /// In nanoseconds.
+    busy: AtomicU64,
+}
+
+fn workers() -> MutexGuard<'static, Vec<Arc<Worker>>> {
+    WORKERS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The stats for the current worker thread, registering it if needed.
+fn with_worker(f: impl FnOnce(&Worker)) {
+    WORKER.with(|worker| {
+        let mut worker = worker.borrow_mut();
+        let session = SESSION.load(Ordering::Relaxed);
+        let worker = match &*worker {
+            Some(w) if w.session == session => w,
+            _ => {
+                let w = Arc::new(Worker {
+                    session,
+                    behaviours: AtomicU64::new(0),
+                    busy: AtomicU64::new(0),
+                });
+                workers().push(w.clone());
+                worker.insert(w)
+            }
+        };
+        f(worker)
+    })
+}
+
+fn nanos(d: Duration) -> u64 {
+    d.as_nanos().try_into().unwrap_or(u64::MAX)
+}
+
+pub(crate) fn reset() {
+    SESSION.fetch_add(1, Ordering::Relaxed);
+    SCHEDULED.store(0, Ordering::Relaxed);
+    COMPLETED.store(0, Ordering::Relaxed);
+    LATENCY.store(0, Ordering::Relaxed);
+    workers().clear();
+}
+
+pub(crate) fn cown_created() {
+    LIVE_COWNS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn cown_freed() {
+    LIVE_COWNS.fetch_sub(1, Ordering::Relaxed);
+}
+
+pub(crate) fn scheduled(n: usize) {
+    SCHEDULED.fetch_add(n as u64, Ordering::Relaxed);
+}
+
+/// When a behaviour was scheduled.
+#[derive(Clone, Copy)]
+pub(crate) struct Scheduled(Instant);
+
+impl Scheduled {
+    pub(crate) fn now() -> Self {
+        Self(Instant::now())
+    }
+}
+
+/// Times a behaviour while it runs.
+pub(crate) struct Running {
+    scheduled: Scheduled,
+    started: Instant,
+}
+
+impl Running {
+    pub(crate) fn start(scheduled: Scheduled) -> Self {
+        Self {
+            scheduled,
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        let busy = nanos(self.started.elapsed());
+        with_worker(|w| {
+            w.behaviours.fetch_add(1, Ordering::Relaxed);
+            w.busy.fetch_add(busy, Ordering::Relaxed);
+        });
+        // Together, so the average latency only covers completed behaviours.
+        LATENCY.fetch_add(nanos(self.started - self.scheduled.0), Ordering::Relaxed);
+        COMPLETED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub(crate) fn snapshot() -> RuntimeStats {
+    let workers = workers()
+        .iter()
+        .map(|w| WorkerStats {
+            behaviours: w.behaviours.load(Ordering::Relaxed),
+            busy: Duration::from_nanos(w.busy.load(Ordering::Relaxed)),
+        })
+        .collect();
+
+    // Read completed first, so it's never more than scheduled. Latency may
+    // include a behaviour that completed since, which is close enough.
+    let behaviours_completed = COMPLETED.load(Ordering::Relaxed);
+    RuntimeStats {
+        behaviours_scheduled: SCHEDULED.load(Ordering::Relaxed).max(behaviours_completed),
+        behaviours_completed,
+        total_latency: Duration::from_nanos(LATENCY.load(Ordering::Relaxed)),
+        live_cowns: LIVE_COWNS.load(Ordering::Relaxed),
+        workers,
+    }
+}
+
+/// A snapshot of the runtime's counters, from
+/// [`Runtime::stats`](crate::Runtime::stats).
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct RuntimeStats {
+    /// Behaviours handed to the runtime this session.
+    pub behaviours_scheduled: u64,
+    /// Behaviours that have finished running this session.
+    pub behaviours_completed: u64,
+    /// Total time between behaviours being scheduled and starting to run,
+    /// across all completed behaviours.
+    pub total_latency: Duration,
+    /// Cowns that are currently alive.
+    pub live_cowns: usize,
+    /// One entry for each worker that has run a behaviour this session.
+    pub workers: Vec<WorkerStats>,
+}
+
+impl RuntimeStats {
+    /// Behaviours that have been scheduled, but not finished: waiting for
+    /// their cowns, waiting for a worker, or running.
+    ///
+    /// This is `behaviours_scheduled - behaviours_completed`, not the depth of
+    /// any of the scheduler's queues.
+    pub fn outstanding(&self) -> u64 {
+        self.behaviours_scheduled - self.behaviours_completed
+    }
+
+    /// Average time from a behaviour being scheduled to it starting, or
+    /// `None` if nothing's finished yet.
+    pub fn average_latency(&self) -> Option<Duration> {
+        let n = u32::try_from(self.behaviours_completed).ok()?;
+        self.total_latency.checked_div(n)
+    }
+}
+
+/// What one worker thread has been doing.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct WorkerStats {
+    /// Behaviours this worker has run.
+    pub behaviours: u64,
+    /// Time spent running behaviours. The rest of the worker's time isn't
+    /// necessarily idle, as it includes the scheduler's own work.
+    pub busy: Duration,
+}
+
+/// Takes a snapshot of the stats at a fixed interval, until dropped.
+///
+/// Created by [`Runtime::sample_stats`](crate::Runtime::sample_stats).
+#[derive(Debug)]
+#[must_use = "sampling stops when this is dropped"]
+pub struct StatsSampler {
+    stop: Option<std::sync::mpsc::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl StatsSampler {
+    pub(crate) fn start(
+        interval: Duration,
+        mut f: impl FnMut(RuntimeStats) + Send + 'static,
+    ) -> Self {
+        let (stop, stopped) = std::sync::mpsc::channel::<()>();
+        let thread = thread::Builder::new()
+            .name("verona-rt-stats".into())
+            .spawn(move || {
+                // Disconnected when the sampler is dropped.
+                while let Err(std::sync::mpsc::RecvTimeoutError::Timeout) =
+                    stopped.recv_timeout(interval)
+                {
+                    f(snapshot());
+                }
+            })
+            .expect("can't start stats thread");
+
+        Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for StatsSampler {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            // A panic in the callback has already been reported.
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Runtime;
+
+    use super::*;
+
+    #[test]
+    fn sampler() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let sampler = Runtime::sample_stats(Duration::from_millis(1), move |stats| {
+            let _ = tx.send(stats);
+        });
+        let first = rx.recv().unwrap();
+        drop(sampler);
+
+        assert!(first.behaviours_completed <= first.behaviours_scheduled);
+        // The sender is dropped with the sampler's thread.
+        while rx.recv().is_ok() {}
+    }
+}
@@ -0,0 +1,138 @@
+//! A mutex and condvar that work with or without `std`.
+//!
+//! With `std`, these are [`std::sync::Mutex`] and [`std::sync::Condvar`],
+//! ignoring poisoning: nothing we protect can be left in a bad state by a
+//! panic. Without `std`, the mutex is a spin lock, and waiting on the condvar
+//! spins, which is fine for the short critical sections we have, but means
+//! threads waiting for the scheduler burn CPU.
+
+#[cfg(feature = "std")]
+mod imp {
+    pub(crate) type MutexGuard<'a, T> = std::sync::MutexGuard<'a, T>;
+
+    pub(crate) struct Mutex<T>(std::sync::Mutex<T>);
+
+    impl<T> Mutex<T> {
+        pub(crate) const fn new(value: T) -> Self {
+            Self(std::sync::Mutex::new(value))
+        }
+
+        pub(crate) fn lock(&self) -> MutexGuard<'_, T> {
+            self.0.lock().unwrap_or_else(|e| e.into_inner())
+        }
+    }
+
+    pub(crate) struct Condvar(std::sync::Condvar);
+
+    impl Condvar {
+        pub(crate) const fn new() -> Self {
+            Self(std::sync::Condvar::new())
+        }
+
+        pub(crate) fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+            self.0.wait(guard).unwrap_or_else(|e| e.into_inner())
+        }
+
+        /// Like [`wait`](Self::wait), but gives up after `timeout`.
+        pub(crate) fn wait_timeout<'a, T>(
+            &self,
+            guard: MutexGuard<'a, T>,
+            timeout: std::time::Duration,
+        ) -> MutexGuard<'a, T> {
+            self.0
+                .wait_timeout(guard, timeout)
+                .unwrap_or_else(|e| e.into_inner())
+                .0
+        }
+
+        pub(crate) fn notify_all(&self) {
+            self.0.notify_all()
+        }
+    }
+}
+
+#[cfg(not(feature = "std"))]
+mod imp {
+    use core::{
+        cell::UnsafeCell,
+        hint, ops,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+
+    pub(crate) struct Mutex<T> {
+        locked: AtomicBool,
+        value: UnsafeCell<T>,
+    }
+
+    // Same bounds as `std::sync::Mutex`.
+    unsafe impl<T: Send> Send for Mutex<T> {}
+    unsafe impl<T: Send> Sync for Mutex<T> {}
+
+    impl<T> Mutex<T> {
+        pub(crate) const fn new(value: T) -> Self {
+            Self {
+                locked: AtomicBool::new(false),
+                value: UnsafeCell::new(value),
+            }
+        }
+
+        pub(crate) fn lock(&self) -> MutexGuard<'_, T> {
+            while self
+                .locked
+                .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+            {
+                while self.locked.load(Ordering::Relaxed) {
+                    hint::spin_loop();
+                }
+            }
+            MutexGuard { mutex: self }
+        }
+    }
+
+    pub(crate) struct MutexGuard<'a, T> {
+        mutex: &'a Mutex<T>,
+    }
+
+    impl<T> ops::Deref for MutexGuard<'_, T> {
+        type Target = T;
+
+        fn deref(&self) -> &T {
+            // SAFETY: We hold the lock.
+            unsafe { &*self.mutex.value.get() }
+        }
+    }
+
+    impl<T> ops::DerefMut for MutexGuard<'_, T> {
+        fn deref_mut(&mut self) -> &mut T {
+            // SAFETY: We hold the lock.
+            unsafe { &mut *self.mutex.value.get() }
+        }
+    }
+
+    impl<T> Drop for MutexGuard<'_, T> {
+        fn drop(&mut self) {
+            self.mutex.locked.store(false, Ordering::Release);
+        }
+    }
+
+    pub(crate) struct Condvar;
+
+    impl Condvar {
+        pub(crate) const fn new() -> Self {
+            Self
+        }
+
+        /// Unlock, and lock again, letting other threads in.
+        pub(crate) fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+            let mutex = guard.mutex;
+            drop(guard);
+            hint::spin_loop();
+            mutex.lock()
+        }
+
+        pub(crate) fn notify_all(&self) {}
+    }
+}
+
+pub(crate) use imp::{Condvar, Mutex, MutexGuard};
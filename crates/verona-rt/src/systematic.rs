@@ -0,0 +1,196 @@
+//! Reproducible systematic testing.
+//!
+//! With the `systematic_testing` feature, verona-rt picks the interleaving of
+//! behaviours with a seeded PRNG, so a given seed always produces the same
+//! schedule. When a seeded session panics, the seed is printed so the failure
+//! can be replayed, either by passing it to [`run`] or by setting the
+//! `VERONA_SEED` environment variable.
+//!
+//! ```rust
+//! # use verona_rt::*;
+//! systematic::run(42, || {
+//!     let v = CownPtr::new(10);
+//!     when(&v, |v| assert_eq!(*v, 10));
+//! });
+//! ```
+//!
+//! To hunt for ordering bugs, [`explore`] runs the same closure under many
+//! seeds, and reports how to replay the first one that fails.
+
+use std::{
+    any::Any,
+    panic::{self, AssertUnwindSafe},
+};
+
+use verona_rt_sys::checked;
+
+use crate::scheduler::SchedulerBuilder;
+
+/// Environment variable that overrides the seed of every session.
+pub const SEED_ENV_VAR: &str = "VERONA_SEED";
+
+/// Run `f` in a scheduler session, with the given seed.
+///
+/// Shorthand for `SchedulerBuilder::new().seed(seed).run(f)`.
+pub fn run<T>(seed: u64, f: impl FnOnce() -> T) -> T {
+    SchedulerBuilder::new().seed(seed).run(f)
+}
+
+/// Run `f` under each of `seeds`, stopping at the first one that panics.
+///
+/// If `VERONA_SEED` is set, only that seed is run.
+///
+/// ## Panics
+///
+/// If `f` panics for any seed. The panic message contains the seed, and a
+/// command to rerun the current test with only that seed.
+///
+/// ```rust
+/// # use verona_rt::*;
+/// systematic::explore(0..100, || {
+///     let v = CownPtr::new(Vec::new());
+///     when(&v, |mut v| v.push(1));
+///     when(&v, |v| assert_eq!(*v, [1]));
+/// });
+/// ```
+pub fn explore(seeds: impl IntoIterator<Item = u64>, f: impl Fn()) {
+    explore_with(SchedulerBuilder::new(), seeds, f)
+}
+
+/// Like [`explore`], but with each session configured by `builder`.
+pub fn explore_with(builder: SchedulerBuilder, seeds: impl IntoIterator<Item = u64>, f: impl Fn()) {
+    let seeds: Box<dyn Iterator<Item = u64>> = match seed_from_env() {
+        Some(seed) => Box::new(std::iter::once(seed)),
+        None => Box::new(seeds.into_iter()),
+    };
+
+    for seed in seeds {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| builder.clone().seed(seed).run(&f)));
+
+        if let Err(payload) = result {
+            panic!(
+                "systematic testing failed with seed {seed}: {}\n\
+                 replay with: {}",
+                panic_message(&*payload),
+                replay_command(seed)
+            );
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s
+    } else {
+        "<non-string panic payload>"
+    }
+}
+
+fn replay_command(seed: u64) -> String {
+    // libtest names each test's thread after the test.
+    let test = match std::thread::current().name() {
+        Some(name) if name != "main" => format!(" -- {name} --exact"),
+        _ => String::new(),
+    };
+    format!("{SEED_ENV_VAR}={seed} cargo test --features systematic_testing{test}")
+}
+
+/// The seed set in `VERONA_SEED`, if any.
+///
+/// ## Panics
+///
+/// If `VERONA_SEED` is set, but isn't a number.
+pub fn seed_from_env() -> Option<u64> {
+    let seed = std::env::var(SEED_ENV_VAR).ok()?;
+    match seed.parse() {
+        Ok(seed) => Some(seed),
+        Err(_) => panic!("{SEED_ENV_VAR}={seed:?} isn't a valid seed"),
+    }
+}
+
+/// Sets the seed for a session, and prints it if the session panics.
+pub(crate) struct SeedReporter(Option<u64>);
+
+impl SeedReporter {
+    /// ## Panics
+    ///
+    /// In debug builds, if the scheduler is already initialized.
+    pub(crate) fn install(seed: Option<u64>) -> Self {
+        let seed = seed_from_env().or(seed);
+        if let Some(seed) = seed {
+            checked::set_seed(seed).unwrap_or_else(|e| panic!("can't set seed: {e}"));
+        }
+        Self(seed)
+    }
+}
+
+impl Drop for SeedReporter {
+    fn drop(&mut self) {
+        if let Some(seed) = self.0 {
+            if std::thread::panicking() {
+                eprintln!(
+                    "systematic testing failed with seed {seed}, \
+                     replay it with {SEED_ENV_VAR}={seed}"
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    };
+
+    use super::*;
+    use crate::{when, CownPtr};
+
+    #[test]
+    fn same_seed_same_schedule() {
+        static ORDER: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+        fn schedule(seed: u64) -> Vec<usize> {
+            SchedulerBuilder::new().threads(4).seed(seed).run(|| {
+                let cowns: Vec<_> = (0..20).map(CownPtr::new).collect();
+                for c in &cowns {
+                    when(c, |c| ORDER.lock().unwrap().push(*c));
+                }
+            });
+            std::mem::take(&mut *ORDER.lock().unwrap())
+        }
+
+        let first = schedule(101);
+        assert_eq!(first.len(), 20);
+        assert_eq!(first, schedule(101));
+    }
+
+    #[test]
+    fn explore_runs_every_seed() {
+        let runs = AtomicUsize::new(0);
+        explore(0..50, || {
+            runs.fetch_add(1, Ordering::SeqCst);
+        });
+        assert_eq!(runs.load(Ordering::SeqCst), 50);
+    }
+
+    #[test]
+    #[should_panic = "failed with seed 13: unlucky"]
+    fn explore_stops_at_failure() {
+        let runs = AtomicUsize::new(10);
+        explore(10..20, || {
+            let unlucky = runs.fetch_add(1, Ordering::SeqCst) == 13;
+            let v = CownPtr::new(());
+            // In a behaviour, so the panic is caught, and resumed once the
+            // scheduler has finished.
+            when(&v, move |_| {
+                if unlucky {
+                    panic!("unlucky");
+                }
+            });
+        });
+    }
+}
@@ -0,0 +1,305 @@
+//! Scheduling behaviours after a delay.
+//!
+//! Timers are kept by a single background thread, started the first time a
+//! timer is created. When a timer fires, that thread schedules its behaviour
+//! like any other external thread. Each pending timer holds an
+//! [`ExternalSourceGuard`], so the scheduler keeps running until every timer
+//! has fired or been cancelled.
+
+use std::{
+    collections::BTreeMap,
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Once,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    cown::CownPtr,
+    runtime::{ExternalSourceGuard, Runtime},
+    sync::{Condvar, Mutex},
+    when::{schedule1, AcquiredCown, Origin},
+};
+
+static TIMERS: Mutex<Timers> = Mutex::new(Timers {
+    pending: BTreeMap::new(),
+    firing: None,
+});
+/// Notified when a timer is added, in case it's the new earliest.
+static ADDED: Condvar = Condvar::new();
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+static START: Once = Once::new();
+
+struct Timers {
+    /// Ordered by when they next fire.
+    ///
+    /// Keyed by the deadline, and then the timer's id, so timers with the
+    /// same deadline fire in the order they were created.
+    pending: BTreeMap<(Instant, u64), Timer>,
+    /// The id of the timer that's firing, which isn't in `pending`, and
+    /// whether it was cancelled meanwhile.
+    firing: Option<(u64, bool)>,
+}
+
+struct Timer {
+    /// Schedules the behaviour.
+    fire: Box<dyn FnMut() + Send>,
+    period: Option<Duration>,
+    _source: ExternalSourceGuard,
+}
+
+fn add(delay: Duration, period: Option<Duration>, fire: Box<dyn FnMut() + Send>) -> u64 {
+    let timer = Timer {
+        fire,
+        period,
+        _source: Runtime::add_external_event_source(),
+    };
+
+    START.call_once(|| {
+        thread::Builder::new()
+            .name("verona-rt-timers".into())
+            .spawn(timer_thread)
+            .expect("can't start timer thread");
+    });
+
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    TIMERS
+        .lock()
+        .pending
+        .insert((Instant::now() + delay, id), timer);
+    ADDED.notify_all();
+    id
+}
+
+fn timer_thread() {
+    let mut timers = TIMERS.lock();
+    loop {
+        let Some((&(deadline, id), _)) = timers.pending.first_key_value() else {
+            timers = ADDED.wait(timers);
+            continue;
+        };
+
+        let now = Instant::now();
+        if deadline > now {
+            timers = ADDED.wait_timeout(timers, deadline - now);
+            continue;
+        }
+
+        // Fire without the lock, so timers can be added and cancelled while
+        // the behaviour is scheduled.
+        let mut timer = timers.pending.remove(&(deadline, id)).unwrap();
+        timers.firing = Some((id, false));
+        drop(timers);
+        // This is the only timer thread, so it mustn't die with the timer.
+        let fired = panic::catch_unwind(AssertUnwindSafe(|| (timer.fire)()));
+        if fired.is_err() {
+            std::eprintln!("timer panicked, cancelling it");
+        }
+
+        timers = TIMERS.lock();
+        let (_, cancelled) = timers.firing.take().unwrap();
+        match timer.period {
+            // Fixed rate: if we fell behind, the missed ticks fire straight
+            // away.
+            Some(period) if fired.is_ok() && !cancelled => {
+                timers.pending.insert((deadline + period, id), timer);
+            }
+            // Otherwise drop the timer, letting the scheduler finish if
+            // nothing else is keeping it running. Outside the lock, as that
+            // may wake whoever's waiting for the session.
+            _ => {
+                drop(timers);
+                drop(timer);
+                timers = TIMERS.lock();
+            }
+        }
+    }
+}
+
+/// Schedule `f` to run on `cown` once `delay` has passed.
+///
+/// The behaviour is scheduled when the timer fires, so it's ordered after any
+/// behaviour on `cown` scheduled before then. The scheduler keeps running
+/// until the timer has fired.
+///
+/// ```rust
+/// # use verona_rt::*;
+/// use std::time::Duration;
+///
+/// with_scheduler(|| {
+///     let v = CownPtr::new(0);
+///     when_after(Duration::from_millis(50), &v, |v| assert_eq!(*v, 1));
+///     when(&v, |mut v| *v += 1);
+/// });
+/// ```
+///
+/// # Panics
+///
+/// Outside of a scheduler session.
+#[track_caller]
+pub fn when_after<T, F>(delay: Duration, cown: &CownPtr<T>, f: F)
+where
+    T: Send + 'static,
+    F: FnOnce(AcquiredCown<'_, T>) + Send + 'static,
+{
+    let origin = Origin::new::<F>(None);
+    let mut pending = Some((cown.clone(), f));
+    add(
+        delay,
+        None,
+        Box::new(move || {
+            // Release our reference once the behaviour is scheduled, rather
+            // than when the timer thread gets round to dropping the timer.
+            if let Some((cown, f)) = pending.take() {
+                schedule1(&cown, f, origin);
+            }
+        }),
+    );
+}
+
+/// Schedule `f` to run on `cown` every `period`, starting one `period` from
+/// now, until the returned handle is cancelled or dropped.
+///
+/// The scheduler keeps running while the timer is active.
+///
+/// # Panics
+///
+/// Outside of a scheduler session.
+#[track_caller]
+pub fn every<T, F>(period: Duration, cown: &CownPtr<T>, f: F) -> TimerHandle
+where
+    T: Send + 'static,
+    F: Fn(AcquiredCown<'_, T>) + Send + Sync + 'static,
+{
+    let origin = Origin::new::<F>(None);
+    let cown = cown.clone();
+    let f = Arc::new(f);
+    let id = add(
+        period,
+        Some(period),
+        Box::new(move || {
+            let f = f.clone();
+            schedule1(&cown, move |c| f(c), origin)
+        }),
+    );
+    TimerHandle { id }
+}
+
+/// A periodic timer, created by [`every`].
+///
+/// Dropping this cancels the timer.
+#[derive(Debug)]
+#[must_use = "the timer is cancelled when this is dropped"]
+pub struct TimerHandle {
+    id: u64,
+}
+
+impl TimerHandle {
+    /// Stop the timer.
+    ///
+    /// Behaviours that have already been scheduled still run, but it won't
+    /// schedule any more.
+    pub fn cancel(self) {}
+}
+
+impl Drop for TimerHandle {
+    fn drop(&mut self) {
+        // Take the timer out under the lock, but drop it (and so maybe let
+        // the scheduler finish) outside it.
+        let timer = {
+            let mut timers = TIMERS.lock();
+            match &mut timers.firing {
+                // The timer thread drops it once it's fired.
+                Some((id, cancelled)) if *id == self.id => {
+                    *cancelled = true;
+                    None
+                }
+                _ => {
+                    let key = timers.pending.keys().find(|&&(_, id)| id == self.id);
+                    let key = key.copied();
+                    key.and_then(|key| timers.pending.remove(&key))
+                }
+            }
+        };
+        drop(timer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use crate::scheduler::SchedulerBuilder;
+
+    use super::*;
+
+    #[test]
+    fn when_after_waits() {
+        static FIRED: Mutex<Option<Instant>> = Mutex::new(None);
+
+        let start = Instant::now();
+        SchedulerBuilder::new().run(|| {
+            let v = CownPtr::new(());
+            when_after(Duration::from_millis(50), &v, |_| {
+                *FIRED.lock() = Some(Instant::now());
+            });
+        });
+
+        // The session can't finish before the timer fires.
+        let fired = FIRED.lock().expect("timer didn't fire");
+        assert!(fired - start >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn every_until_cancelled() {
+        static TICKS: AtomicUsize = AtomicUsize::new(0);
+
+        SchedulerBuilder::new().threads(2).run(|| {
+            let v = CownPtr::new(());
+            let handle = every(Duration::from_millis(5), &v, |_| {
+                TICKS.fetch_add(1, Ordering::SeqCst);
+            });
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                handle.cancel();
+            });
+        });
+
+        assert!(TICKS.load(Ordering::SeqCst) > 0);
+    }
+
+    #[test]
+    fn panicking_timer() {
+        static FIRED: AtomicUsize = AtomicUsize::new(0);
+
+        SchedulerBuilder::new().run(|| {
+            add(Duration::ZERO, None, Box::new(|| panic!("timer panicked")));
+            let v = CownPtr::new(());
+            when_after(Duration::from_millis(10), &v, |_| {
+                FIRED.fetch_add(1, Ordering::SeqCst);
+            });
+        });
+
+        // The timer thread carried on.
+        assert_eq!(FIRED.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn cancel_before_firing() {
+        static TICKS: AtomicUsize = AtomicUsize::new(0);
+
+        SchedulerBuilder::new().run(|| {
+            let v = CownPtr::new(());
+            every(Duration::from_millis(5), &v, |_| {
+                TICKS.fetch_add(1, Ordering::SeqCst);
+            })
+            .cancel();
+        });
+
+        assert_eq!(TICKS.load(Ordering::SeqCst), 0);
+    }
+}
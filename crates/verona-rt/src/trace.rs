@@ -0,0 +1,219 @@
+//! Recording when and where behaviours run, for viewing in Perfetto.
+//!
+//! With the `trace` feature, every behaviour is recorded with the worker
+//! thread it ran on, when it started and finished, the cowns it acquired, and
+//! where it was scheduled. Behaviours are named by
+//! [`BehaviourBuilder::name`](crate::BehaviourBuilder::name), or otherwise by
+//! the type of their closure.
+//! The trace can be written as [Chrome Trace Event] JSON, which can be opened
+//! in <https://ui.perfetto.dev> or `chrome://tracing`:
+//!
+//! - On demand, with [`write_json`] or [`save`].
+//! - When each scheduler session finishes, if the `VERONA_TRACE` environment
+//!   variable is set to a path.
+//!
+//! Events are kept until [`clear`] is called, so the trace covers every
+//! session so far.
+//!
+//! See also docs/trace.md.
+//!
+//! [Chrome Trace Event]: https://docs.google.com/document/d/1CvAClvFfyA5R-PhYUmn5OOQtYMH4h6I0nSsKchNAySU
+
+use std::{
+    cell::RefCell,
+    fmt::Write as _,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard, OnceLock},
+    thread,
+    time::Instant,
+};
+
+use verona_rt_sys as ffi;
+
+use crate::{json::escape, when::Origin};
+
+/// Environment variable naming where to save the trace when a session
+/// finishes.
+pub const TRACE_ENV_VAR: &str = "VERONA_TRACE";
+
+/// Every thread that has run a behaviour.
+static THREADS: Mutex<Vec<Arc<ThreadEvents>>> = Mutex::new(Vec::new());
+/// Timestamps are relative to this.
+static EPOCH: OnceLock<Instant> = OnceLock::new();
+
+thread_local! {
+    static LOCAL: RefCell<Option<Arc<ThreadEvents>>> = const { RefCell::new(None) };
+}
+
+/// The events from one thread.
+///
+/// Each thread only records its own events, so the lock is uncontended except
+/// while writing the trace.
+struct ThreadEvents {
+    tid: usize,
+    name: String,
+    events: Mutex<Vec<Event>>,
+}
+
+struct Event {
+    origin: Origin,
+    begin: Instant,
+    end: Instant,
+    cowns: Box<[usize]>,
+}
+
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn record(event: Event) {
+    LOCAL.with(|local| {
+        let mut local = local.borrow_mut();
+        let local = local.get_or_insert_with(|| {
+            let mut threads = lock(&THREADS);
+            let tid = threads.len();
+            let name = match thread::current().name() {
+                Some(name) => name.to_owned(),
+                None => format!("worker {tid}"),
+            };
+            let events = Arc::new(ThreadEvents {
+                tid,
+                name,
+                events: Mutex::new(Vec::new()),
+            });
+            threads.push(events.clone());
+            events
+        });
+        lock(&local.events).push(event);
+    })
+}
+
+/// Records a behaviour while it runs.
+pub(crate) struct Span {
+    origin: Origin,
+    cowns: Box<[usize]>,
+    begin: Instant,
+}
+
+impl Span {
+    pub(crate) fn begin(origin: &Origin, cowns: &[ffi::AcquiredCown]) -> Self {
+        EPOCH.get_or_init(Instant::now);
+        Self {
+            origin: *origin,
+            cowns: cowns.iter().map(|c| c.addr() as usize).collect(),
+            begin: Instant::now(),
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        record(Event {
+            origin: self.origin,
+            begin: self.begin,
+            end: Instant::now(),
+            cowns: std::mem::take(&mut self.cowns),
+        });
+    }
+}
+
+/// Write everything recorded so far as Chrome Trace Event JSON.
+pub fn write_json(mut w: impl Write) -> io::Result<()> {
+    let epoch = *EPOCH.get_or_init(Instant::now);
+    let micros = |t: Instant| (t - epoch).as_secs_f64() * 1e6;
+
+    w.write_all(b"{\"traceEvents\":[")?;
+    let mut first = true;
+    let mut sep = |w: &mut dyn Write| -> io::Result<()> {
+        if !std::mem::take(&mut first) {
+            w.write_all(b",")?;
+        }
+        w.write_all(b"\n")
+    };
+
+    for thread in lock(&THREADS).iter() {
+        sep(&mut w)?;
+        write!(
+            w,
+            r#"{{"ph":"M","name":"thread_name","pid":1,"tid":{},"args":{{"name":"{}"}}}}"#,
+            thread.tid,
+            escape(&thread.name),
+        )?;
+
+        for event in lock(&thread.events).iter() {
+            let mut cowns = String::new();
+            for (i, cown) in event.cowns.iter().enumerate() {
+                let sep = if i == 0 { "" } else { "," };
+                write!(cowns, r#"{sep}"{cown:#x}""#).unwrap();
+            }
+
+            sep(&mut w)?;
+            write!(
+                w,
+                r#"{{"ph":"X","name":"{}","cat":"behaviour","pid":1,"tid":{},"ts":{:.3},"dur":{:.3},"args":{{"cowns":[{}],"location":"{}"}}}}"#,
+                escape(event.origin.name()),
+                thread.tid,
+                micros(event.begin),
+                micros(event.end) - micros(event.begin),
+                cowns,
+                escape(&event.origin.location.to_string()),
+            )?;
+        }
+    }
+
+    w.write_all(b"\n]}\n")
+}
+
+/// Write the trace to `path`, replacing it if it exists.
+pub fn save(path: impl AsRef<Path>) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write_json(&mut file)?;
+    file.flush()
+}
+
+/// Forget everything recorded so far.
+pub fn clear() {
+    for thread in lock(&THREADS).iter() {
+        lock(&thread.events).clear();
+    }
+}
+
+/// Called when a session finishes, to save the trace if asked to.
+pub(crate) fn session_finished() {
+    if let Some(path) = std::env::var_os(TRACE_ENV_VAR) {
+        if let Err(e) = save(&path) {
+            eprintln!("can't save trace to {}: {e}", Path::new(&path).display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{scheduler::SchedulerBuilder, when, when2, CownPtr};
+
+    use super::*;
+
+    #[test]
+    fn records_behaviours() {
+        let (a, b) = SchedulerBuilder::new().threads(2).run(|| {
+            let a = CownPtr::new(0);
+            let b = CownPtr::new(0);
+            when(&a, |mut a| *a += 1);
+            when2(&a, &b, |a, mut b| *b += *a);
+            (format!("{a:p}"), format!("{b:p}"))
+        });
+
+        let mut json = Vec::new();
+        write_json(&mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+
+        assert!(json.starts_with(r#"{"traceEvents":["#), "{json}");
+        assert!(json.contains(r#""ph":"X""#), "{json}");
+        assert!(json.contains(r#""ph":"M""#), "{json}");
+        assert!(json.contains("records_behaviours::{{closure}}"), "{json}");
+        assert!(json.contains(&format!(r#"["{a}","{b}"]"#)), "{json}");
+        assert!(json.contains("src/trace.rs:"), "{json}");
+    }
+}
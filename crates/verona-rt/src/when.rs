@@ -1,9 +1,16 @@
-use core::{fmt, marker::PhantomData, mem, ops};
-use std::ops::Deref;
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::{fmt, marker::PhantomData, ops, ops::Deref, panic::Location};
+#[cfg(feature = "std")]
+use std::{
+    any::Any,
+    format,
+    panic::{self, AssertUnwindSafe},
+    string::String,
+};
 
-use verona_rt_sys as ffi;
+use verona_rt_sys::{self as ffi, checked};
 
-use crate::cown::CownPtr;
+use crate::{cown::CownPtr, sync::Mutex};
 
 pub struct AcquiredCown<'a, T> {
     // TODO: As an optimization, point to the `T`, and roll the pointer back to
@@ -50,49 +57,590 @@ unsafe fn make_aq<'a, T>(aq: &mut ffi::AcquiredCown) -> AcquiredCown<'a, T> {
     }
 }
 
-extern "C" fn trampoline1<T>(aq: &mut ffi::AcquiredCown, data: *mut ()) {
-    unsafe {
-        let func = mem::transmute::<_, UseFunc1<T>>(data);
-        func(make_aq(aq));
+/// The first panic from a behaviour in the current session.
+#[cfg(feature = "std")]
+static BEHAVIOUR_PANIC: Mutex<Option<Box<dyn Any + Send>>> = Mutex::new(None);
+
+/// Run a behaviour's body, catching any panic.
+///
+/// Unwinding into C++ would abort the process, so instead we stash the panic,
+/// and resume it once the scheduler has finished. As that's on another thread,
+/// the message says which behaviour panicked.
+#[cfg(feature = "std")]
+fn catch_behaviour_panic(origin: &Origin, f: impl FnOnce()) {
+    if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(f)) {
+        let message = match payload.downcast_ref::<&str>() {
+            Some(s) => Some(String::from(*s)),
+            None => payload.downcast_ref::<String>().cloned(),
+        };
+        let payload = match message {
+            Some(message) => Box::new(format!("{message}\n  in {origin}")),
+            // Can't add to it without losing the original.
+            None => payload,
+        };
+        BEHAVIOUR_PANIC.lock().get_or_insert(payload);
     }
 }
-extern "C" fn trampoline2<T, U>(
+
+/// Without `std` we can't catch panics, so `no_std` programs must abort on
+/// panic.
+#[cfg(not(feature = "std"))]
+fn catch_behaviour_panic(_origin: &Origin, f: impl FnOnce()) {
+    f()
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn take_behaviour_panic() -> Option<Box<dyn Any + Send>> {
+    BEHAVIOUR_PANIC.lock().take()
+}
+
+/// A behaviour's closure, and what we know about it, boxed up and passed to
+/// the trampolines as `data`.
+struct Body<F> {
+    func: F,
+    meta: Meta,
+}
+
+/// Where a behaviour came from.
+///
+/// Shown when it panics, and in logs, traces and the dependency graph.
+#[derive(Clone, Copy)]
+pub(crate) struct Origin {
+    /// Where it was scheduled.
+    pub(crate) location: &'static Location<'static>,
+    /// From [`BehaviourBuilder::name`].
+    pub(crate) label: Option<&'static str>,
+    /// The type of the closure.
+    pub(crate) closure: &'static str,
+}
+
+impl Origin {
+    #[track_caller]
+    pub(crate) fn new<F>(label: Option<&'static str>) -> Self {
+        Self {
+            location: Location::caller(),
+            label,
+            closure: core::any::type_name::<F>(),
+        }
+    }
+
+    /// The label if there is one, or the closure's type.
+    #[cfg(any(feature = "trace", feature = "dep_graph"))]
+    pub(crate) fn name(&self) -> &'static str {
+        self.label.unwrap_or(self.closure)
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.label {
+            Some(label) => write!(f, "behaviour `{label}` scheduled at {}", self.location),
+            None => write!(f, "behaviour scheduled at {}", self.location),
+        }
+    }
+}
+
+/// What we know about a behaviour, besides its closure.
+#[derive(Clone, Copy)]
+struct Meta {
+    origin: Origin,
+    #[cfg(feature = "stats")]
+    scheduled: crate::stats::Scheduled,
+    #[cfg(feature = "dep_graph")]
+    node: u64,
+}
+
+impl Meta {
+    fn new(origin: Origin) -> Self {
+        Self {
+            origin,
+            #[cfg(feature = "stats")]
+            scheduled: crate::stats::Scheduled::now(),
+            #[cfg(feature = "dep_graph")]
+            node: crate::dep_graph::next_id(),
+        }
+    }
+}
+
+/// Box up `func`, to be passed to a trampoline.
+fn into_data<F>(func: F, meta: Meta) -> *mut () {
+    Box::into_raw(Box::new(Body { func, meta })) as *mut ()
+}
+
+/// Run `f` with the closure from `data`, as a behaviour on `cowns`.
+///
+/// ## Safety
+///
+/// - `data` must come from [`into_data::<F>`], and not be used again.
+unsafe fn run_body<F>(data: *mut (), _cowns: &[ffi::AcquiredCown], f: impl FnOnce(F)) {
+    let Body { func, meta } = *Box::from_raw(data as *mut Body<F>);
+    #[cfg(feature = "dep_graph")]
+    crate::dep_graph::started(meta.node);
+    #[cfg(feature = "stats")]
+    let _running = crate::stats::Running::start(meta.scheduled);
+    #[cfg(feature = "trace")]
+    let _span = crate::trace::Span::begin(&meta.origin, _cowns);
+    #[cfg(feature = "std")]
+    let _context = crate::context::Behaviour::enter(_cowns);
+    crate::log::behaviour("running", &meta.origin);
+    catch_behaviour_panic(&meta.origin, || f(func));
+}
+
+/// Run the boxed closure at `data`.
+///
+/// ## Safety
+///
+/// - `data` must come from [`into_data::<F>`], and not be used again.
+extern "C" fn trampoline1<T, F>(aq: &mut ffi::AcquiredCown, data: *mut ())
+where
+    F: FnOnce(AcquiredCown<'_, T>),
+{
+    unsafe { run_body(data, &[*aq], |func: F| func(make_aq(aq))) }
+}
+/// Like [`trampoline1`], for two cowns.
+extern "C" fn trampoline2<T, U, F>(
     a1: &mut ffi::AcquiredCown,
     a2: &mut ffi::AcquiredCown,
     data: *mut (),
-) {
-    unsafe {
-        let func: UseFunc2<T, U> = mem::transmute(data);
-        func(make_aq(a1), make_aq(a2));
-    }
+) where
+    F: FnOnce(AcquiredCown<'_, T>, AcquiredCown<'_, U>),
+{
+    unsafe { run_body(data, &[*a1, *a2], |func: F| func(make_aq(a1), make_aq(a2))) }
+}
+
+/// Drop the boxed closure at `data`, for when it won't be run.
+///
+/// ## Safety
+///
+/// - `data` must come from [`into_data::<F>`], and not be used again.
+unsafe fn drop_closure<F>(data: *mut ()) {
+    drop(Box::from_raw(data as *mut Body<F>));
+}
+
+/// Count `n` behaviours as scheduled, once they've been handed to the
+/// runtime.
+fn scheduled(_n: usize) {
+    #[cfg(feature = "stats")]
+    crate::stats::scheduled(_n);
+}
+
+/// Schedule `f` to run once `cown` is available.
+///
+/// `f` may run on any worker thread, with access to the cown's data, so both
+/// must be `Send`. To give the behaviour a name, use [`BehaviourBuilder`].
+#[track_caller]
+pub fn when<T, F>(cown: &CownPtr<T>, f: F)
+where
+    T: Send,
+    F: FnOnce(AcquiredCown<'_, T>) + Send + 'static,
+{
+    schedule1(cown, f, Origin::new::<F>(None))
+}
+
+/// Schedule `f` to run once `c1` and `c2` are both available.
+///
+/// # Panics
+///
+/// If `c1` and `c2` are the same cown.
+#[track_caller]
+pub fn when2<T, U, F>(c1: &CownPtr<T>, c2: &CownPtr<U>, f: F)
+where
+    T: Send,
+    U: Send,
+    F: FnOnce(AcquiredCown<'_, T>, AcquiredCown<'_, U>) + Send + 'static,
+{
+    schedule2(c1, c2, f, Origin::new::<F>(None))
+}
+
+/// Like [`when`], but the behaviour can be cancelled until it starts.
+///
+/// ```rust
+/// # use verona_rt::*;
+/// # with_scheduler(|| {
+/// let v = CownPtr::new(0);
+/// let token = when_cancellable(&v, |mut v| *v += 1);
+/// if token.cancel() {
+///     when(&v, |v| assert_eq!(*v, 0));
+/// }
+/// # });
+/// ```
+#[track_caller]
+pub fn when_cancellable<T, F>(cown: &CownPtr<T>, f: F) -> CancelToken
+where
+    T: Send,
+    F: FnOnce(AcquiredCown<'_, T>) + Send + 'static,
+{
+    cancellable1(cown, f, Origin::new::<F>(None))
+}
+
+/// Like [`when2`], but the behaviour can be cancelled until it starts.
+#[track_caller]
+pub fn when2_cancellable<T, U, F>(c1: &CownPtr<T>, c2: &CownPtr<U>, f: F) -> CancelToken
+where
+    T: Send,
+    U: Send,
+    F: FnOnce(AcquiredCown<'_, T>, AcquiredCown<'_, U>) + Send + 'static,
+{
+    cancellable2(c1, c2, f, Origin::new::<F>(None))
 }
 
-type UseFunc1<T> = for<'a> fn(AcquiredCown<'a, T>);
-type UseFunc2<T, U> = for<'a, 'b> fn(AcquiredCown<'a, T>, AcquiredCown<'b, U>);
+/// Options for scheduling a behaviour.
+///
+/// ```rust
+/// # use verona_rt::*;
+/// # with_scheduler(|| {
+/// let cache = CownPtr::new(vec![1, 2, 3]);
+/// BehaviourBuilder::new()
+///     .name("flush-cache")
+///     .when(&cache, |mut cache| cache.clear());
+/// # });
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct BehaviourBuilder {
+    name: Option<&'static str>,
+}
 
-pub fn when<T>(cown: &CownPtr<T>, f: UseFunc1<T>) {
-    let trampoline = trampoline1::<T>;
+impl BehaviourBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-    unsafe {
-        ffi::boxcar_when1(&cown.cown_ptr, trampoline, f as _);
+    /// A label for the behaviour, shown alongside where it was scheduled if
+    /// it panics, and in logs, traces and the dependency graph.
+    pub fn name(mut self, name: &'static str) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// See [`when`].
+    #[track_caller]
+    pub fn when<T, F>(self, cown: &CownPtr<T>, f: F)
+    where
+        T: Send,
+        F: FnOnce(AcquiredCown<'_, T>) + Send + 'static,
+    {
+        schedule1(cown, f, Origin::new::<F>(self.name))
+    }
+
+    /// See [`when2`].
+    #[track_caller]
+    pub fn when2<T, U, F>(self, c1: &CownPtr<T>, c2: &CownPtr<U>, f: F)
+    where
+        T: Send,
+        U: Send,
+        F: FnOnce(AcquiredCown<'_, T>, AcquiredCown<'_, U>) + Send + 'static,
+    {
+        schedule2(c1, c2, f, Origin::new::<F>(self.name))
+    }
+
+    /// See [`when_cancellable`].
+    #[track_caller]
+    pub fn when_cancellable<T, F>(self, cown: &CownPtr<T>, f: F) -> CancelToken
+    where
+        T: Send,
+        F: FnOnce(AcquiredCown<'_, T>) + Send + 'static,
+    {
+        cancellable1(cown, f, Origin::new::<F>(self.name))
+    }
+
+    /// See [`when2_cancellable`].
+    #[track_caller]
+    pub fn when2_cancellable<T, U, F>(self, c1: &CownPtr<T>, c2: &CownPtr<U>, f: F) -> CancelToken
+    where
+        T: Send,
+        U: Send,
+        F: FnOnce(AcquiredCown<'_, T>, AcquiredCown<'_, U>) + Send + 'static,
+    {
+        cancellable2(c1, c2, f, Origin::new::<F>(self.name))
     }
 }
 
-pub fn when2<T, U>(c1: &CownPtr<T>, c2: &CownPtr<U>, f: UseFunc2<T, U>) {
+pub(crate) fn schedule1<T, F>(cown: &CownPtr<T>, f: F, origin: Origin)
+where
+    T: Send,
+    F: FnOnce(AcquiredCown<'_, T>) + Send + 'static,
+{
+    let meta = Meta::new(origin);
+    let data = into_data(f, meta);
+    let trampoline = trampoline1::<T, F>;
+
+    #[cfg(feature = "dep_graph")]
+    crate::dep_graph::reserve([(meta.node, &origin, vec![cown.cown_ptr.addr() as usize])]);
+    if let Err(e) = unsafe { checked::when1(&cown.cown_ptr, trampoline, data) } {
+        #[cfg(feature = "dep_graph")]
+        crate::dep_graph::unreserve(&[meta.node]);
+        unsafe { drop_closure::<F>(data) };
+        panic!("can't schedule {origin}: {e}")
+    }
+    scheduled(1);
+}
+
+fn schedule2<T, U, F>(c1: &CownPtr<T>, c2: &CownPtr<U>, f: F, origin: Origin)
+where
+    T: Send,
+    U: Send,
+    F: FnOnce(AcquiredCown<'_, T>, AcquiredCown<'_, U>) + Send + 'static,
+{
     // So we don't let the func acquire the same cown twice.
     // See also: https://github.com/microsoft/verona-rt/pull/30
     assert_ne!(
         c1.cown_ptr.addr(),
         c2.cown_ptr.addr(),
-        "used the same cown twice"
+        "used the same cown twice in {origin}"
     );
 
-    let trampoline = trampoline2::<T, U>;
-    unsafe {
-        ffi::boxcar_when2(&c1.cown_ptr, &c2.cown_ptr, trampoline, f as _);
+    let meta = Meta::new(origin);
+    let data = into_data(f, meta);
+    let trampoline = trampoline2::<T, U, F>;
+
+    #[cfg(feature = "dep_graph")]
+    crate::dep_graph::reserve([(
+        meta.node,
+        &origin,
+        vec![c1.cown_ptr.addr() as usize, c2.cown_ptr.addr() as usize],
+    )]);
+    if let Err(e) = unsafe { checked::when2(&c1.cown_ptr, &c2.cown_ptr, trampoline, data) } {
+        #[cfg(feature = "dep_graph")]
+        crate::dep_graph::unreserve(&[meta.node]);
+        unsafe { drop_closure::<F>(data) };
+        panic!("can't schedule {origin}: {e}")
+    }
+    scheduled(1);
+}
+
+fn cancellable1<T, F>(cown: &CownPtr<T>, f: F, origin: Origin) -> CancelToken
+where
+    T: Send,
+    F: FnOnce(AcquiredCown<'_, T>) + Send + 'static,
+{
+    let slot = Arc::new(Mutex::new(Some(f)));
+    let token = CancelToken { slot: slot.clone() };
+    let f = move |c: AcquiredCown<'_, T>| {
+        let f = slot.lock().take();
+        if let Some(f) = f {
+            f(c)
+        }
+    };
+    schedule1(cown, f, origin);
+    token
+}
+
+fn cancellable2<T, U, F>(c1: &CownPtr<T>, c2: &CownPtr<U>, f: F, origin: Origin) -> CancelToken
+where
+    T: Send,
+    U: Send,
+    F: FnOnce(AcquiredCown<'_, T>, AcquiredCown<'_, U>) + Send + 'static,
+{
+    let slot = Arc::new(Mutex::new(Some(f)));
+    let token = CancelToken { slot: slot.clone() };
+    let f = move |c1: AcquiredCown<'_, T>, c2: AcquiredCown<'_, U>| {
+        let f = slot.lock().take();
+        if let Some(f) = f {
+            f(c1, c2)
+        }
+    };
+    schedule2(c1, c2, f, origin);
+    token
+}
+
+/// Cancels a behaviour scheduled by [`when_cancellable`] or
+/// [`when2_cancellable`].
+///
+/// Dropping the token doesn't cancel the behaviour.
+#[derive(Clone)]
+pub struct CancelToken {
+    slot: Arc<dyn Cancel + Send + Sync>,
+}
+
+impl CancelToken {
+    /// Cancel the behaviour, if it hasn't started yet.
+    ///
+    /// The closure is dropped straight away. The behaviour still takes its
+    /// turn on its cowns, but does nothing, so behaviours scheduled after it
+    /// aren't held up any longer than they would be otherwise.
+    ///
+    /// Returns `true` if the behaviour was cancelled, or `false` if it had
+    /// already started (or been cancelled).
+    pub fn cancel(&self) -> bool {
+        self.slot.cancel()
+    }
+}
+
+impl fmt::Debug for CancelToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancelToken").finish_non_exhaustive()
     }
 }
 
+/// Type erasure for the closure in a [`CancelToken`].
+trait Cancel {
+    fn cancel(&self) -> bool;
+}
+
+impl<F> Cancel for Mutex<Option<F>> {
+    fn cancel(&self) -> bool {
+        let f = self.lock().take();
+        // Dropped outside the lock, in case it's big.
+        f.is_some()
+    }
+}
+
+/// Schedule several behaviours in one atomic step.
+///
+/// The behaviours added to the [`Batch`] are ordered as if they were
+/// scheduled one after another, but no other behaviour can be ordered between
+/// them on any of their cowns.
+///
+/// ```rust
+/// # use verona_rt::*;
+/// # with_scheduler(|| {
+/// let from = CownPtr::new(100);
+/// let to = CownPtr::new(0);
+///
+/// schedule_many(|s| {
+///     s.when(&from, |mut from| *from -= 10);
+///     s.when2(&from, &to, |from, mut to| {
+///         assert_eq!(*from, 90);
+///         *to += 10;
+///     });
+/// });
+/// # });
+/// ```
+pub fn schedule_many<'a>(f: impl FnOnce(&mut Batch<'a>)) {
+    let mut batch = Batch {
+        behaviours: Vec::new(),
+        cowns: Vec::new(),
+        drops: Vec::new(),
+        metas: Vec::new(),
+        _marker: PhantomData,
+    };
+    f(&mut batch);
+    batch.schedule();
+}
+
+/// Behaviours to be scheduled together by [`schedule_many`].
+pub struct Batch<'a> {
+    behaviours: Vec<ffi::BatchBehaviour>,
+    // Backing storage for `BatchBehaviour::cowns`. Boxed so the pointers stay
+    // valid when this grows.
+    cowns: Vec<Box<[*const ffi::CownPtr]>>,
+    // How to drop each behaviour's closure, if the batch is never scheduled.
+    drops: Vec<unsafe fn(*mut ())>,
+    metas: Vec<Meta>,
+    _marker: PhantomData<&'a ()>,
+}
+
+impl<'a> Batch<'a> {
+    /// Add a behaviour on one cown to the batch. See [`when`].
+    #[track_caller]
+    pub fn when<T, F>(&mut self, cown: &'a CownPtr<T>, f: F)
+    where
+        T: Send,
+        F: FnOnce(AcquiredCown<'_, T>) + Send + 'static,
+    {
+        self.push(
+            Box::new([&cown.cown_ptr]),
+            batch_trampoline1::<T, F>,
+            drop_closure::<F>,
+            f,
+            Origin::new::<F>(None),
+        );
+    }
+
+    /// Add a behaviour on two cowns to the batch. See [`when2`].
+    #[track_caller]
+    pub fn when2<T, U, F>(&mut self, c1: &'a CownPtr<T>, c2: &'a CownPtr<U>, f: F)
+    where
+        T: Send,
+        U: Send,
+        F: FnOnce(AcquiredCown<'_, T>, AcquiredCown<'_, U>) + Send + 'static,
+    {
+        let origin = Origin::new::<F>(None);
+        assert_ne!(
+            c1.cown_ptr.addr(),
+            c2.cown_ptr.addr(),
+            "used the same cown twice in {origin}"
+        );
+
+        self.push(
+            Box::new([&c1.cown_ptr, &c2.cown_ptr]),
+            batch_trampoline2::<T, U, F>,
+            drop_closure::<F>,
+            f,
+            origin,
+        );
+    }
+
+    fn push<F>(
+        &mut self,
+        cowns: Box<[*const ffi::CownPtr]>,
+        trampoline: extern "C" fn(*mut ffi::AcquiredCown, *mut ()),
+        drop: unsafe fn(*mut ()),
+        f: F,
+        origin: Origin,
+    ) {
+        let meta = Meta::new(origin);
+        self.behaviours.push(ffi::BatchBehaviour {
+            cowns: cowns.as_ptr(),
+            count: cowns.len(),
+            func: trampoline,
+            data: into_data(f, meta),
+        });
+        self.cowns.push(cowns);
+        self.drops.push(drop);
+        self.metas.push(meta);
+    }
+
+    fn schedule(mut self) {
+        if self.behaviours.is_empty() {
+            return;
+        }
+
+        // One reservation for the whole batch, so it's recorded in order.
+        #[cfg(feature = "dep_graph")]
+        crate::dep_graph::reserve(self.metas.iter().zip(&self.cowns).map(|(meta, cowns)| {
+            let cowns = cowns
+                .iter()
+                .map(|&c| unsafe { (*c).addr() as usize })
+                .collect();
+            (meta.node, &meta.origin, cowns)
+        }));
+        let result = unsafe { checked::schedule_many(&self.behaviours) };
+        if let Err(e) = result {
+            #[cfg(feature = "dep_graph")]
+            crate::dep_graph::unreserve(
+                &self.metas.iter().map(|meta| meta.node).collect::<Vec<_>>(),
+            );
+            panic!("can't schedule behaviours: {e}");
+        }
+        // The runtime owns the closures now.
+        scheduled(self.behaviours.len());
+        self.behaviours.clear();
+    }
+}
+
+impl Drop for Batch<'_> {
+    fn drop(&mut self) {
+        for (behaviour, drop) in self.behaviours.iter().zip(&self.drops) {
+            // SAFETY: Never scheduled, so never run.
+            unsafe { drop(behaviour.data) }
+        }
+    }
+}
+
+extern "C" fn batch_trampoline1<T, F>(aq: *mut ffi::AcquiredCown, data: *mut ())
+where
+    F: FnOnce(AcquiredCown<'_, T>),
+{
+    unsafe { trampoline1::<T, F>(&mut *aq, data) }
+}
+extern "C" fn batch_trampoline2<T, U, F>(aq: *mut ffi::AcquiredCown, data: *mut ())
+where
+    F: FnOnce(AcquiredCown<'_, T>, AcquiredCown<'_, U>),
+{
+    unsafe { trampoline2::<T, U, F>(&mut *aq, &mut *aq.add(1), data) }
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::atomic::{AtomicU8, Ordering};
@@ -197,6 +745,53 @@ mod tests {
         })
     }
 
+    #[test]
+    fn batch() {
+        scheduler::with(|| {
+            let a = CownPtr::new(Vec::new());
+            let b = CownPtr::new(Vec::new());
+
+            schedule_many(|s| {
+                s.when(&a, |mut a| a.push(1));
+                s.when2(&a, &b, |mut a, mut b| {
+                    a.push(2);
+                    b.push(2);
+                });
+                s.when(&b, |mut b| b.push(3));
+            });
+
+            when(&a, |a| assert_eq!(*a, [1, 2]));
+            when(&b, |b| assert_eq!(*b, [2, 3]));
+        })
+    }
+
+    #[test]
+    fn batch_empty() {
+        scheduler::with(|| schedule_many(|_| {}))
+    }
+
+    #[test]
+    #[should_panic = "behaviour panicked"]
+    fn behaviour_panic_propagates() {
+        // Exclusive, so the panic can't go to another test sharing the
+        // runtime.
+        scheduler::SchedulerBuilder::new().run(|| {
+            let x = CownPtr::new(1);
+            when(&x, |_| panic!("behaviour panicked"));
+        })
+    }
+
+    #[test]
+    #[should_panic = "in behaviour `named` scheduled at"]
+    fn panic_names_behaviour() {
+        scheduler::SchedulerBuilder::new().run(|| {
+            let x = CownPtr::new(1);
+            BehaviourBuilder::new()
+                .name("named")
+                .when(&x, |_| panic!("behaviour panicked"));
+        })
+    }
+
     #[test]
     fn fmt_acquired() {
         scheduler::with(|| {
@@ -208,4 +803,54 @@ mod tests {
             })
         })
     }
+
+    #[test]
+    fn captures() {
+        static DONE: AtomicU8 = AtomicU8::new(0);
+
+        scheduler::with(|| {
+            let x = CownPtr::new(String::from("foo"));
+            let suffix = String::from("bar");
+            when(&x, move |mut x| {
+                x.push_str(&suffix);
+                assert_eq!(*x, "foobar");
+                DONE.fetch_add(1, Ordering::SeqCst);
+            });
+        });
+
+        assert_eq!(DONE.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn cancel() {
+        static RUN: AtomicU8 = AtomicU8::new(0);
+
+        let token = scheduler::with(|| {
+            let x = CownPtr::new(0);
+            let captured = std::sync::Arc::new(());
+
+            let moved = captured.clone();
+            let token = when_cancellable(&x, move |_| {
+                drop(moved);
+                RUN.fetch_add(1, Ordering::SeqCst);
+            });
+            // Workers don't start until the closure returns.
+            assert!(token.cancel());
+            assert!(!token.cancel());
+            assert_eq!(std::sync::Arc::strong_count(&captured), 1);
+
+            // Later behaviours aren't held up.
+            when(&x, |x| {
+                assert_eq!(*x, 0);
+                RUN.fetch_add(1, Ordering::SeqCst);
+            });
+
+            when_cancellable(&x, |_| {
+                RUN.fetch_add(1, Ordering::SeqCst);
+            })
+        });
+
+        assert_eq!(RUN.load(Ordering::SeqCst), 2);
+        assert!(!token.cancel());
+    }
 }
@@ -0,0 +1,204 @@
+//! Hooks into the scheduler's worker threads.
+//!
+//! The worker threads are created by the C++ runtime, so Rust never sees them
+//! start. Instead, the scheduler runs with a startup function, which each
+//! worker calls before it runs any behaviours. That gives the worker its
+//! [`WorkerId`], and calls [`SchedulerBuilder::on_thread_start`].
+//!
+//! There's no matching hook for when a worker stops, so we use a thread-local
+//! whose destructor calls [`SchedulerBuilder::on_thread_stop`] as the thread
+//! exits. The thread calling `scheduler_run` may be a worker too, but doesn't
+//! exit, so its thread-local is dropped once the scheduler has finished, and
+//! any [`Affinity`](crate::Affinity) it was given is undone.
+//!
+//! [`SchedulerBuilder::on_thread_start`]: crate::SchedulerBuilder::on_thread_start
+//! [`SchedulerBuilder::on_thread_stop`]: crate::SchedulerBuilder::on_thread_stop
+
+use core::fmt;
+
+use verona_rt_sys::checked::{self, SchedulerError};
+
+#[cfg(feature = "std")]
+use std::{
+    cell::RefCell,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+#[cfg(all(feature = "std", target_os = "linux"))]
+use crate::affinity;
+
+#[cfg(feature = "std")]
+type Hook = Arc<dyn Fn(WorkerId) + Send + Sync>;
+
+/// What to do on the workers of one session as they start and stop.
+#[derive(Clone)]
+pub(crate) struct Hooks {
+    #[cfg(feature = "std")]
+    pub(crate) start: Option<Hook>,
+    #[cfg(feature = "std")]
+    pub(crate) stop: Option<Hook>,
+    /// The cores to pin workers to, in order. Worked out from the
+    /// [`Affinity`](crate::Affinity) when it's set, so a failure is reported
+    /// there rather than on a worker.
+    #[cfg(all(feature = "std", target_os = "linux"))]
+    pub(crate) cores: Option<Vec<usize>>,
+}
+
+impl Hooks {
+    pub(crate) const NONE: Self = Self {
+        #[cfg(feature = "std")]
+        start: None,
+        #[cfg(feature = "std")]
+        stop: None,
+        #[cfg(all(feature = "std", target_os = "linux"))]
+        cores: None,
+    };
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut f = f.debug_struct("Hooks");
+        #[cfg(feature = "std")]
+        f.field("on_thread_start", &self.start.is_some())
+            .field("on_thread_stop", &self.stop.is_some());
+        #[cfg(all(feature = "std", target_os = "linux"))]
+        f.field("cores", &self.cores);
+        f.finish()
+    }
+}
+
+/// Identifies one of the scheduler's worker threads, within a session.
+///
+/// Ids count up from zero, in the order the workers started.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct WorkerId(usize);
+
+#[cfg(feature = "std")]
+impl WorkerId {
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for WorkerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "worker {}", self.0)
+    }
+}
+
+/// The next worker's id.
+#[cfg(feature = "std")]
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(feature = "std")]
+thread_local! {
+    static CURRENT: RefCell<Option<Worker>> = const { RefCell::new(None) };
+}
+
+/// The current thread's place in the scheduler.
+#[cfg(feature = "std")]
+struct Worker {
+    id: WorkerId,
+    stop: Option<Hook>,
+}
+
+#[cfg(feature = "std")]
+impl Drop for Worker {
+    fn drop(&mut self) {
+        if let Some(stop) = &self.stop {
+            stop(self.id);
+        }
+    }
+}
+
+/// The worker thread this is running on, or `None` if it's not one of the
+/// scheduler's workers.
+///
+/// Inside a behaviour, this is always `Some`.
+#[cfg(feature = "std")]
+pub fn current_worker() -> Option<WorkerId> {
+    CURRENT
+        .try_with(|current| current.borrow().as_ref().map(|w| w.id))
+        .ok()
+        .flatten()
+}
+
+/// Passed to [`start`] by [`run`].
+#[cfg(feature = "std")]
+struct Startup<'a> {
+    hooks: &'a Hooks,
+}
+
+/// Called by each worker before it runs any behaviours.
+#[cfg(feature = "std")]
+extern "C" fn start(startup: *mut ()) {
+    // SAFETY: `run` passes a `Startup`, which outlives the scheduler running.
+    let startup = unsafe { &*(startup as *const Startup<'_>) };
+    let hooks = startup.hooks;
+    let id = WorkerId(NEXT_ID.fetch_add(1, Ordering::Relaxed));
+
+    #[cfg(target_os = "linux")]
+    if let Some(cores) = &hooks.cores {
+        let core = cores[id.0 % cores.len()];
+        if let Err(e) = affinity::set(&[core]) {
+            std::eprintln!("can't pin {id} to core {core}: {e}");
+        }
+    }
+
+    let previous = CURRENT.with(|current| {
+        current.replace(Some(Worker {
+            id,
+            stop: hooks.stop.clone(),
+        }))
+    });
+    drop(previous);
+    if let Some(start) = &hooks.start {
+        start(id);
+    }
+}
+
+/// Run the scheduler until all behaviours have finished, calling `hooks` on
+/// each worker.
+#[cfg(feature = "std")]
+pub(crate) fn run(hooks: &Hooks) -> Result<(), SchedulerError> {
+    let startup = Startup { hooks };
+    // So we can undo pinning, in case this thread is a worker.
+    #[cfg(target_os = "linux")]
+    let unpinned = hooks
+        .cores
+        .as_ref()
+        .and_then(|_| match affinity::allowed() {
+            Ok(cores) => Some(cores),
+            Err(e) => {
+                std::eprintln!("can't get the scheduler thread's cores, so won't unpin it: {e}");
+                None
+            }
+        });
+
+    NEXT_ID.store(0, Ordering::Relaxed);
+    // SAFETY: `start` only reads `startup`, which is `Sync`. A panic in a
+    // hook can't unwind out of an `extern "C"` function, so aborts instead.
+    let result =
+        unsafe { checked::run_with_startup(start, &startup as *const Startup<'_> as *mut ()) };
+
+    // In case this thread was a worker.
+    let worker = CURRENT.with(|current| current.take());
+    drop(worker);
+    #[cfg(target_os = "linux")]
+    if let Some(cores) = unpinned {
+        if let Err(e) = affinity::set(&cores) {
+            std::eprintln!("can't unpin scheduler thread: {e}");
+        }
+    }
+    result
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn run(_hooks: &Hooks) -> Result<(), SchedulerError> {
+    checked::run()
+}
@@ -0,0 +1,22 @@
+#![cfg(feature = "dep_graph")]
+
+use verona_rt::{dep_graph, when, CownPtr, SchedulerBuilder};
+
+// Clearing the graph is global, so this must be the only test in the process.
+
+#[test]
+fn clear_forgets_last_behaviours() {
+    SchedulerBuilder::new().run(|| {
+        let a = CownPtr::new(0);
+        when(&a, |_| {});
+        dep_graph::clear();
+        when(&a, |_| {});
+    });
+
+    let mut dot = Vec::new();
+    dep_graph::write_dot(&mut dot).unwrap();
+    let dot = String::from_utf8(dot).unwrap();
+    // Only the second behaviour, with no edge to the forgotten first.
+    assert_eq!(dot.matches("[label=").count(), 1, "{dot}");
+    assert!(!dot.contains(" -> "), "{dot}");
+}
@@ -6,9 +6,7 @@ use verona_rt::{log, with_scheduler};
 
 #[test]
 fn main() {
-    unsafe {
-        verona_rt_sys::enable_logging();
-    }
+    verona_rt_sys::checked::enable_logging();
 
     with_scheduler(|| {
         log(cstr::cstr!("Hello World\n"));
@@ -22,7 +20,5 @@ fn main() {
         log(v);
     });
 
-    unsafe {
-        verona_rt_sys::dump_flight_recorder();
-    }
+    verona_rt_sys::checked::dump_flight_recorder();
 }
@@ -1,3 +1,6 @@
+// Under ASan, snmalloc passes allocations through, so can't see leaks.
+#![cfg(not(feature = "asan"))]
+
 use verona_rt::with_leak_detector;
 use verona_rt::CownPtr;
 
@@ -0,0 +1,38 @@
+// Under ASan, snmalloc passes allocations through, so can't see leaks.
+#![cfg(not(feature = "asan"))]
+
+use verona_rt::{check_leaks, CownPtr, SchedulerBuilder};
+
+use std::{
+    mem,
+    panic::{self, AssertUnwindSafe},
+};
+
+// Like leak-detector-basic, this must be the only test in the process.
+
+#[test]
+fn report_lists_leaked_cown() {
+    // Cowns are only recorded while leak detection is on.
+    let mut addr = String::new();
+    let panic = panic::catch_unwind(AssertUnwindSafe(|| {
+        SchedulerBuilder::new().detect_leaks(true).run(|| {
+            let v = CownPtr::new(666u64);
+            addr = format!("{v:p}");
+            mem::forget(v);
+        })
+    }))
+    .unwrap_err();
+    let message = panic.downcast::<String>().unwrap();
+    assert!(message.contains("u64"), "{message}");
+
+    // Still there, as the detector is global.
+    let report = check_leaks().unwrap_err();
+    let [cown] = report.cowns() else {
+        panic!("expected exactly one leaked cown, got {report}");
+    };
+
+    assert_eq!(format!("{:p}", cown.addr()), addr);
+    assert_eq!(cown.type_name(), "u64");
+    assert!(cown.size() > mem::size_of::<u64>());
+    assert!(report.to_string().contains("u64"), "{report}");
+}
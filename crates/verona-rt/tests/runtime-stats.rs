@@ -0,0 +1,37 @@
+#![cfg(feature = "stats")]
+
+use std::time::Duration;
+
+use verona_rt::{when, CownPtr, Runtime, SchedulerBuilder};
+
+// The stats are global, so this must be the only test in the process.
+
+#[test]
+fn counts_behaviours() {
+    let during = SchedulerBuilder::new().threads(2).run(|| {
+        let a = CownPtr::new(0);
+        let b = CownPtr::new(0);
+        for _ in 0..10 {
+            when(&a, |mut a| *a += 1);
+            when(&b, |mut b| *b += 1);
+        }
+
+        // Nothing runs until the closure returns.
+        Runtime::stats()
+    });
+
+    assert_eq!(during.behaviours_scheduled, 20);
+    assert_eq!(during.behaviours_completed, 0);
+    assert_eq!(during.outstanding(), 20);
+    assert_eq!(during.live_cowns, 2);
+    assert_eq!(during.average_latency(), None);
+
+    let after = Runtime::stats();
+    assert_eq!(after.behaviours_completed, 20);
+    assert_eq!(after.outstanding(), 0);
+    assert_eq!(after.live_cowns, 0);
+    assert!(after.average_latency().is_some());
+    assert!(!after.workers.is_empty());
+    assert!(after.workers.iter().all(|w| w.busy > Duration::ZERO));
+    assert_eq!(after.workers.iter().map(|w| w.behaviours).sum::<u64>(), 20);
+}
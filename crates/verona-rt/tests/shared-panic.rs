@@ -0,0 +1,36 @@
+use std::{any::Any, thread};
+
+use verona_rt::{when, CownPtr, RuntimeHandle};
+
+// The panic is reported to everyone in the shared session, so this must be
+// the only test in the process.
+
+fn message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(s) => *s,
+        Err(payload) => payload.downcast::<&str>().unwrap().to_string(),
+    }
+}
+
+#[test]
+fn every_participant_panics() {
+    let runtime = RuntimeHandle::acquire();
+    let other = runtime.clone();
+    let (panicker, other) = thread::scope(|s| {
+        let panicker = s.spawn(move || {
+            let v = CownPtr::new(());
+            when(&v, |_| panic!("boom"));
+            runtime.wait();
+        });
+        let other = s.spawn(move || other.wait());
+        (panicker.join(), other.join())
+    });
+
+    for result in [panicker, other] {
+        let message = message(result.unwrap_err());
+        assert!(message.contains("boom"), "{message}");
+    }
+
+    // The next session didn't fail, so mustn't see the old panic.
+    RuntimeHandle::acquire().wait();
+}
@@ -0,0 +1,59 @@
+use verona_rt::{when, when2, CownPtr};
+
+#[verona_rt::test]
+fn plain() {
+    let v = CownPtr::new(10);
+    when(&v, |v| assert_eq!(*v, 10));
+}
+
+#[verona_rt::test(threads = 4)]
+fn many_threads() {
+    let a = CownPtr::new(0);
+    let b = CownPtr::new(0);
+    for _ in 0..100 {
+        when2(&a, &b, |mut a, mut b| {
+            *a += 1;
+            *b += 1;
+        });
+    }
+    when2(&a, &b, |a, b| {
+        assert_eq!(*a, 100);
+        assert_eq!(*b, 100);
+    });
+}
+
+#[verona_rt::test(leak_check)]
+fn leak_check() {
+    let v = CownPtr::new(String::from("hello"));
+    let v2 = v.clone();
+    when(&v2, |mut s| s.push_str(" world"));
+}
+
+#[verona_rt::test]
+fn returns_value() -> Result<(), String> {
+    let v = CownPtr::new(());
+    drop(v);
+    Ok(())
+}
+
+#[verona_rt::test]
+#[should_panic = "from a behaviour"]
+fn behaviour_panic() {
+    let v = CownPtr::new(());
+    when(&v, |_| panic!("from a behaviour"));
+}
+
+#[cfg(feature = "systematic_testing")]
+#[verona_rt::test(seeds = 0..20, threads = 2)]
+fn seeds() {
+    let v = CownPtr::new(Vec::new());
+    when(&v, |mut v| v.push(1));
+    when(&v, |v| assert_eq!(*v, [1]));
+}
+
+#[cfg(feature = "systematic_testing")]
+#[verona_rt::test(seed = 7)]
+fn seed() {
+    let v = CownPtr::new(7);
+    when(&v, |v| assert_eq!(*v, 7));
+}